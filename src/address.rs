@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+// Version bytes of pay-to-public-key-hash and pay-to-script-hash addresses
+const MAINNET_VERSIONS: (u8, u8) = (0x35, 0x37);
+const TESTNET_VERSIONS: (u8, u8) = (0x6f, 0xc4);
+const HASH_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+impl Network {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mainnet" => Some(Network::Mainnet),
+            "testnet" => Some(Network::Testnet),
+            _ => None,
+        }
+    }
+
+    fn versions(&self) -> (u8, u8) {
+        match self {
+            Network::Mainnet => MAINNET_VERSIONS,
+            Network::Testnet => TESTNET_VERSIONS,
+        }
+    }
+
+    pub fn public_key_hash_address(&self, public_key_hash: &[u8]) -> String {
+        encode(self.versions().0, public_key_hash)
+    }
+
+    pub fn script_hash_address(&self, script_hash: &[u8]) -> String {
+        encode(self.versions().1, script_hash)
+    }
+
+    // Rejects malformed addresses and addresses of other networks
+    pub fn validate(&self, address: &str) -> Result<(), Error> {
+        match Address::decode(address) {
+            Some((network, _)) if network == *self => Ok(()),
+            _ => Err(Error::new("Invalid address")),
+        }
+    }
+}
+
+fn encode(version: u8, hash: &[u8]) -> String {
+    bs58::encode(hash).with_check_version(version).into_string()
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Address {
+    PublicKeyHash(Vec<u8>),
+    ScriptHash(Vec<u8>),
+}
+
+impl Address {
+    // Decodes a Base58Check address, failing on a bad checksum or an unknown version
+    pub fn decode(address: &str) -> Option<(Network, Self)> {
+        let bytes = bs58::decode(address).with_check(None).into_vec().ok()?;
+        let (version, hash) = bytes.split_first()?;
+        if hash.len() != HASH_SIZE {
+            return None;
+        }
+        [Network::Mainnet, Network::Testnet]
+            .into_iter()
+            .find_map(|network| match network.versions() {
+                (p2pkh, _) if p2pkh == *version => {
+                    Some((network, Address::PublicKeyHash(hash.to_vec())))
+                }
+                (_, p2sh) if p2sh == *version => {
+                    Some((network, Address::ScriptHash(hash.to_vec())))
+                }
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_round_trips_through_base58check() {
+        let hash = [7; HASH_SIZE];
+        let address = Network::Mainnet.public_key_hash_address(&hash);
+        assert_eq!(
+            Address::decode(&address),
+            Some((Network::Mainnet, Address::PublicKeyHash(hash.to_vec())))
+        );
+
+        let address = Network::Testnet.script_hash_address(&hash);
+        assert_eq!(
+            Address::decode(&address),
+            Some((Network::Testnet, Address::ScriptHash(hash.to_vec())))
+        );
+    }
+
+    #[test]
+    fn address_with_typo_is_rejected() {
+        let address = Network::Mainnet.public_key_hash_address(&[7; HASH_SIZE]);
+        let last = if address.ends_with('a') { "b" } else { "a" };
+        let typo = format!("{}{}", &address[..address.len() - 1], last);
+        assert!(Network::Mainnet.validate(&address).is_ok());
+        assert!(Network::Mainnet.validate(&typo).is_err());
+    }
+
+    #[test]
+    fn address_of_other_network_is_rejected() {
+        let address = Network::Testnet.public_key_hash_address(&[7; HASH_SIZE]);
+        let err = Network::Mainnet.validate(&address).unwrap_err();
+        assert_eq!(err.msg, "Invalid address");
+    }
+}
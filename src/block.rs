@@ -1,8 +1,13 @@
-use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::{hashable::Hashable, Transaction};
 
+// Consensus bound of the canonical bytes of a block
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+// Room kept out of block templates for the header and the coinbase transaction
+pub const BLOCK_RESERVED_SIZE: usize = 1_000;
+
 #[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
 pub struct Block {
     pub hash: String,
@@ -20,57 +25,133 @@ impl Block {
         prev_hash: &str,
         height: u64,
         difficulty: u16,
+        timestamp: i64,
         mempool: &mut Vec<Transaction>,
     ) -> Self {
-        let mut hash = String::from("");
-        let target = std::iter::repeat("0")
-            .take(difficulty.into())
-            .collect::<String>();
+        let target = "0".repeat(difficulty.into());
         let txns = create_txns(address, mempool);
         let mut block = Block {
             prev_hash: prev_hash.to_string(),
             hash: "".to_string(),
-            height: height,
-            difficulty: difficulty,
+            height,
+            difficulty,
             nonce: 0,
-            timestamp: Utc::now().timestamp(),
+            timestamp,
             transactions: txns,
         };
 
+        // Only the header changes while mining
+        let mut header = block.header();
         loop {
+            let hash = header.hash();
             if hash.starts_with(&target) {
                 block.hash = hash;
+                block.nonce = header.nonce;
                 break;
             }
-            hash = block.hash();
-            block.nonce += 1;
+            header.nonce += 1;
         }
         block
     }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            hash: self.hash.clone(),
+            prev_hash: self.prev_hash.clone(),
+            height: self.height,
+            difficulty: self.difficulty,
+            nonce: self.nonce,
+            timestamp: self.timestamp,
+            txns_hash: txns_hash(&self.transactions),
+        }
+    }
+
+    // Bytes of the header and the transactions
+    pub fn size(&self) -> usize {
+        self.bytes().len()
+            + self
+                .transactions
+                .iter()
+                .map(|txn| txn.size())
+                .sum::<usize>()
+    }
+
+    pub fn work(&self) -> u128 {
+        work(self.difficulty)
+    }
+
+    pub fn has_valid_proof(&self) -> bool {
+        self.hash == self.hash() && self.hash.starts_with(&"0".repeat(self.difficulty.into()))
+    }
 }
 
 impl Hashable for Block {
+    fn bytes(&self) -> Vec<u8> {
+        self.header().bytes()
+    }
+}
+
+// What a block hash commits to, so the proof of work can be checked without the transactions
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
+pub struct BlockHeader {
+    pub hash: String,
+    pub prev_hash: String,
+    pub height: u64,
+    pub difficulty: u16,
+    pub nonce: u64,
+    pub timestamp: i64,
+    pub txns_hash: String,
+}
+
+impl BlockHeader {
+    pub fn work(&self) -> u128 {
+        work(self.difficulty)
+    }
+
+    pub fn has_valid_proof(&self) -> bool {
+        self.hash == self.hash() && self.hash.starts_with(&"0".repeat(self.difficulty.into()))
+    }
+}
+
+impl Hashable for BlockHeader {
     fn bytes(&self) -> Vec<u8> {
         let mut bytes = vec![];
         bytes.append(&mut self.prev_hash.to_string().into_bytes());
         bytes.append(&mut self.height.to_le_bytes().to_vec());
         bytes.append(&mut self.difficulty.to_le_bytes().to_vec());
         bytes.append(&mut self.nonce.to_le_bytes().to_vec());
-        bytes.append(
-            &mut self
-                .transactions
-                .iter()
-                .flat_map(|txn| txn.bytes())
-                .collect::<Vec<u8>>(),
-        );
+        bytes.append(&mut self.timestamp.to_le_bytes().to_vec());
+        bytes.append(&mut self.txns_hash.to_string().into_bytes());
         bytes
     }
 }
 
+fn txns_hash(txns: &[Transaction]) -> String {
+    let bytes: Vec<u8> = txns.iter().flat_map(|txn| txn.signed_bytes()).collect();
+    format!("{:x}", Sha256::digest(&bytes))
+}
+
+// Expected number of hashes tried to mine a block, each difficulty step
+// being one more leading hex zero
+fn work(difficulty: u16) -> u128 {
+    1u128
+        .checked_shl(4 * u32::from(difficulty))
+        .unwrap_or(u128::MAX)
+}
+
+// Templates come parents first, so the transactions which fit make a valid block
+// The coinbase collects the fees of the transactions along with the reward
 fn create_txns(address: &str, mempool: &mut Vec<Transaction>) -> Vec<Transaction> {
     let mut txns = vec![];
-    let coinbase_txn = Transaction::from_coinbase(address);
-    txns.push(coinbase_txn);
-    txns.append(mempool);
+    let mut size = 0;
+    for txn in mempool.drain(..) {
+        size += txn.size();
+        if size > MAX_BLOCK_SIZE - BLOCK_RESERVED_SIZE {
+            break;
+        }
+        txns.push(txn);
+    }
+    let fees = txns.iter().map(|txn| txn.fee()).sum();
+    txns.insert(0, Transaction::from_coinbase(address, fees));
     txns
 }
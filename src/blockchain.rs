@@ -1,38 +1,60 @@
-use p256::ecdsa::{
-    signature::{Signature, Verifier},
-    VerifyingKey,
-};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::{cmp::Reverse, collections::HashSet, sync::Arc};
 
 use crate::{
-    block::Block,
+    block::{Block, BlockHeader, BLOCK_RESERVED_SIZE, MAX_BLOCK_SIZE},
+    clock::AdjustedClock,
     error::Error,
+    fees::{FeeEstimator, DEFAULT_TARGET_BLOCKS},
+    hashable::Hashable,
+    mempool::{Mempool, MempoolConfig, MempoolEntry},
+    orphans::OrphanPool,
+    params::ChainParams,
+    policy::Policy,
     repo::BaseRepository,
-    transaction::{Transaction, TxnIn, TxnOut, UTxnOut},
+    script::{is_true, verify_script, Op, Script},
+    sync::{HeaderSync, SyncStatus},
+    transaction::{
+        fee_for_size, BranchAndBound, Htlc, LargestFirst, Transaction, TransactionBuilder, TxnIn,
+        TxnOut, UTxnOut, UtxoEntry, MINER_REWARD,
+    },
     Wallet,
 };
 
 const DIFFICULTY_INTERVAL: u64 = 5;
 const TIME_THRESHOLD: i64 = 36000;
 const ALLOWED_BUFFER: i64 = 7200;
+// Number of recent blocks whose median timestamp is the median time past
+const MEDIAN_TIME_SPAN: usize = 11;
+// Seconds a block timestamp can be ahead of the adjusted time
+const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+// Bound of unconfirmed transactions a mempool transaction can depend on
+const MAX_UNCONFIRMED_ANCESTORS: usize = 25;
+// Most recent blocks a block locator lists one by one
+const LOCATOR_DENSE_HASHES: usize = 10;
 
-macro_rules! unwrap_or_return_false {
-    ( $e:expr ) => {
-        match $e {
-            Ok(x) => x,
-            Err(_) => return false,
-        }
-    };
+// A transaction can replace conflicting ones if they all opted in to replacement,
+// and it pays a strictly higher fee in total and per byte
+fn check_replacement(txn: &Transaction, conflicts: &[Transaction]) -> Result<(), Error> {
+    if conflicts.iter().any(|conflict| !conflict.replaceable) {
+        return Err(Error::new("Transaction conflicts with the mempool"));
+    }
+    let conflicts_fee: u64 = conflicts.iter().map(|conflict| conflict.fee()).sum();
+    let higher_fee_rate = conflicts
+        .iter()
+        .all(|conflict| txn.fee_rate() > conflict.fee_rate());
+    if !conflicts.is_empty() && (txn.fee() <= conflicts_fee || !higher_fee_rate) {
+        return Err(Error::new("Replacement must pay a higher fee"));
+    }
+    Ok(())
 }
 
-fn verify_msg(public_key_str: &str, msg: &str, signature_str: &str) -> bool {
-    let public_key_as_bytes = unwrap_or_return_false!(hex::decode(public_key_str));
-    let public_key = unwrap_or_return_false!(VerifyingKey::from_sec1_bytes(&public_key_as_bytes));
-    let signature_as_bytes = unwrap_or_return_false!(hex::decode(signature_str));
-    let signature = unwrap_or_return_false!(Signature::from_bytes(&signature_as_bytes));
-    let msg_as_bytes = unwrap_or_return_false!(hex::decode(msg));
-    public_key.verify(&msg_as_bytes, &signature).is_ok()
+// Whether the selector on top takes the claim branch of the HTLC, which like
+// the IF of the script is any true value
+fn is_htlc_claim(script_sig: &Script) -> bool {
+    let pushed_data = script_sig.pushed_data();
+    pushed_data.len() >= 2 && pushed_data.last().is_some_and(|selector| is_true(selector))
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -40,7 +62,6 @@ pub struct BlockChainSnapshot {
     pub newest_hash: String,
     pub height: u64,
     pub difficulty: u16,
-    pub mempool: Vec<Transaction>,
 }
 
 impl BlockChainSnapshot {
@@ -49,47 +70,359 @@ impl BlockChainSnapshot {
             newest_hash: String::from(""),
             height: 0,
             difficulty: 1,
-            mempool: vec![],
         }
     }
 }
 
+impl Default for BlockChainSnapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TipStatus {
+    Active,
+    // Fully validated only when the node switches to it
+    ValidFork,
+    // Has a block marked invalid
+    Invalid,
+    // Only the headers of the branch are known
+    HeadersOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChainTip {
+    pub hash: String,
+    pub height: u64,
+    // Work of the blocks from the first one up to the tip
+    pub chain_work: u128,
+    // Blocks since the branch forked off the active chain
+    pub branch_length: u64,
+    pub status: TipStatus,
+}
+
+// Why the node couldn't switch to a branch
+enum SwitchError {
+    // The first block of the branch which failed validation, and why
+    Invalid(String, Error),
+    // The active chain couldn't be rolled back to where the branch forks off
+    Disconnect(Error),
+}
+
 pub struct BlockChain {
     repo: Box<dyn BaseRepository>,
     snapshot: BlockChainSnapshot,
+    mempool: Mempool,
+    policy: Policy,
+    fee_estimator: FeeEstimator,
+    clock: AdjustedClock,
+    params: ChainParams,
+    tips: HashSet<String>,
+    invalid_blocks: HashSet<String>,
+    orphans: OrphanPool,
+    sync: HeaderSync,
+    // Blocks the last checkpoint builds on, which can be assumed valid
+    assumed_valid: HashSet<String>,
 }
 
 impl BlockChain {
     pub fn load(repo: Box<dyn BaseRepository>) -> Self {
-        match repo.load_snapshot() {
-            Some(snapshot) => Self { repo, snapshot },
+        let mut mempool = Mempool::default();
+        for entry in repo.load_mempool().into_iter() {
+            mempool.insert(entry.txn, entry.time, entry.height);
+        }
+        let snapshot = match repo.load_snapshot() {
+            Some(snapshot) => snapshot,
             None => {
                 let snapshot = BlockChainSnapshot::new();
                 repo.save_snapshot(&snapshot).unwrap();
-                let blockchain = BlockChain { repo, snapshot };
-                blockchain
+                snapshot
+            }
+        };
+        let mut chain = Self {
+            fee_estimator: repo.load_fee_estimator().unwrap_or_default(),
+            tips: repo.load_tips().into_iter().collect(),
+            invalid_blocks: repo.load_invalid_blocks().into_iter().collect(),
+            repo,
+            snapshot,
+            mempool,
+            policy: Policy::default(),
+            clock: AdjustedClock::new(),
+            params: ChainParams::default(),
+            orphans: OrphanPool::new(),
+            sync: HeaderSync::new(),
+            assumed_valid: HashSet::new(),
+        };
+        // Chains saved before the UTXO set, tips and indexes existed are reindexed
+        let newest_hash = chain.snapshot.newest_hash.clone();
+        let coinbase_hash = chain
+            .newest_block()
+            .and_then(|block| block.transactions.first().map(|txn| txn.hash.clone()))
+            .unwrap_or_default();
+        if !newest_hash.is_empty()
+            && (chain.repo.get_undo(&newest_hash).is_none()
+                || chain.tips.is_empty()
+                || chain.repo.get_active_hash(chain.snapshot.height).is_none()
+                || chain.repo.get_txn_block(&coinbase_hash).is_none())
+        {
+            let blocks = chain.all_blocks();
+            chain.reindex(&blocks);
+        }
+        chain.revalidate_mempool();
+        chain
+    }
+
+    // Rebuilds the UTXO set, undo data and indexes from `blocks`, the active chain
+    // newest first. The blocks stay stored, along with the side branches
+    fn reindex(&mut self, blocks: &[Block]) {
+        self.snapshot = BlockChainSnapshot::new();
+        for block in blocks.iter().rev() {
+            // Outputs left from the old UTXO set are created again by the block
+            for txn in block.transactions.iter() {
+                for idx in 0..txn.txn_outs.len() {
+                    self.repo.remove_utxo(&txn.hash, idx as i64).unwrap();
+                }
+            }
+            self.connect_block(block).unwrap();
+        }
+        if self.tips.is_empty() && !self.snapshot.newest_hash.is_empty() {
+            self.tips.insert(self.snapshot.newest_hash.clone());
+            let tips: Vec<String> = self.tips.iter().cloned().collect();
+            self.repo.save_tips(&tips).unwrap();
+        }
+    }
+
+    pub fn set_mempool_config(&mut self, config: MempoolConfig) {
+        self.mempool.set_config(config);
+        self.revalidate_mempool();
+    }
+
+    pub fn set_params(&mut self, params: ChainParams) {
+        self.params = params;
+        self.assumed_valid.clear();
+        self.update_assumed_valid();
+    }
+
+    // Collects the chain leading to the last checkpoint once its header is known
+    fn update_assumed_valid(&mut self) {
+        if !self.params.assume_valid || !self.assumed_valid.is_empty() {
+            return;
+        }
+        let Some((_, checkpoint_hash)) = self.params.last_checkpoint() else {
+            return;
+        };
+        let mut hashes = HashSet::new();
+        let mut hash_cursor = checkpoint_hash.to_string();
+        while let Some(header) = self.header(&hash_cursor) {
+            hashes.insert(header.hash);
+            if header.height == 1 {
+                self.assumed_valid = hashes;
+                return;
             }
+            hash_cursor = header.prev_hash;
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+        self.revalidate_mempool();
+    }
+
+    // Drops pending transactions which are no longer valid on top of the current tip
+    fn revalidate_mempool(&mut self) {
+        let entries = self.mempool.entries();
+        self.mempool.clear();
+        for entry in entries.into_iter() {
+            let _ = self.accept_to_mempool(entry.txn, entry.time, entry.height);
         }
+        self.save_mempool();
+    }
+
+    fn save_mempool(&self) {
+        self.repo.save_mempool(&self.mempool.entries()).unwrap();
+    }
+
+    // Writes the mempool out when the node shuts down
+    pub fn dump_mempool(&self) -> Result<(), Error> {
+        self.repo
+            .dump_mempool()
+            .map_err(|_| Error::new("Cannot save the mempool"))
+    }
+
+    // Network-adjusted time, which block timestamps are made and checked against
+    pub fn adjusted_time(&self) -> i64 {
+        self.clock.now()
+    }
+
+    pub fn clock(&self) -> &AdjustedClock {
+        &self.clock
+    }
+
+    // Records the time a peer reported in its handshake
+    pub fn add_peer_time(&mut self, peer: &str, peer_time: i64) {
+        self.clock
+            .add_sample(peer, peer_time.saturating_sub(Utc::now().timestamp()));
     }
 
     pub fn mine_block(&mut self, address: &str) -> Block {
         let difficulty = self.calc_difficulty();
+        let timestamp = self.adjusted_time().max(self.median_time_past() + 1);
         let block = Block::mine(
             address,
             self.snapshot.newest_hash.as_str(),
             self.snapshot.height + 1,
             difficulty,
-            &mut self.snapshot.mempool,
+            timestamp,
+            &mut self.block_template(),
         );
-        self.update_snapshot(&block);
-        self.clear_mempool();
-        self.repo.save_block(&block).unwrap();
-        self.repo.save_snapshot(&self.snapshot).unwrap();
+        self.connect_block(&block).unwrap();
+        self.clear_mempool(&block);
         block
     }
 
-    fn clear_mempool(&mut self) {
-        self.snapshot.mempool.clear();
+    // Moves the tip to `block`, spending its inputs out of the UTXO set
+    // and keeping the spent outputs as its undo data
+    fn connect_block(&mut self, block: &Block) -> Result<(), Error> {
+        // Outputs are never overwritten, or a transaction reusing the hash of another
+        // would take over its unspent outputs
+        if self.has_unspent_outputs(block) {
+            return Err(Error::new("Transaction outputs already exist"));
+        }
+        let mut spent = vec![];
+        for txn in block.transactions.iter() {
+            if !txn.is_coinbase() {
+                for txn_in in txn.txn_ins.iter() {
+                    if let Some(entry) = self.repo.get_utxo(&txn_in.txn_hash, txn_in.idx) {
+                        self.repo.remove_utxo(&txn_in.txn_hash, txn_in.idx).unwrap();
+                        spent.push(entry);
+                    }
+                }
+            }
+            for (idx, txn_out) in txn.txn_outs.iter().enumerate() {
+                if let Some(payload) = txn_out.script_pubkey.data_payload() {
+                    self.repo.save_data_txn(payload, &txn.hash).unwrap();
+                }
+                if txn_out.script_pubkey.is_unspendable() {
+                    continue;
+                }
+                let entry = UtxoEntry::new(&txn.hash, idx as i64, txn_out.clone(), block.height);
+                self.repo.save_utxo(&entry).unwrap();
+            }
+        }
+        self.repo.save_undo(&block.hash, &spent).unwrap();
+        self.repo
+            .save_active_hash(block.height, &block.hash)
+            .unwrap();
+        for txn in block.transactions.iter() {
+            self.repo.save_txn_block(&txn.hash, &block.hash).unwrap();
+        }
+        self.store_block(block);
+        self.update_snapshot(block);
+        self.repo.save_snapshot(&self.snapshot).unwrap();
+        Ok(())
+    }
+
+    fn has_unspent_outputs(&self, block: &Block) -> bool {
+        block.transactions.iter().any(|txn| {
+            (0..txn.txn_outs.len()).any(|idx| self.repo.get_utxo(&txn.hash, idx as i64).is_some())
+        })
+    }
+
+    // Saves `block`, which replaces its parent as the tip of their branch
+    fn store_block(&mut self, block: &Block) {
+        if self.repo.get_block(block.hash.clone()).is_some() {
+            return;
+        }
+        self.repo.save_block(block).unwrap();
+        self.sync.remove(&block.hash);
+        self.tips.remove(&block.prev_hash);
+        self.tips.insert(block.hash.clone());
+        let tips: Vec<String> = self.tips.iter().cloned().collect();
+        self.repo.save_tips(&tips).unwrap();
+    }
+
+    // Moves the tip back to its parent, restoring the outputs the block spent
+    // and returning its transactions to the mempool
+    pub fn disconnect_tip(&mut self) -> Result<Block, Error> {
+        let block = self
+            .newest_block()
+            .ok_or_else(|| Error::new("Chain is empty"))?;
+        let spent = self
+            .repo
+            .get_undo(&block.hash)
+            .ok_or_else(|| Error::new("Undo data not found"))?;
+        for txn in block.transactions.iter() {
+            for (idx, txn_out) in txn.txn_outs.iter().enumerate() {
+                self.repo.remove_utxo(&txn.hash, idx as i64).unwrap();
+                if let Some(payload) = txn_out.script_pubkey.data_payload() {
+                    self.repo.remove_data_txn(payload, &txn.hash).unwrap();
+                }
+            }
+            self.repo.remove_txn_block(&txn.hash).unwrap();
+        }
+        // Outputs created and spent within the block weren't there before it
+        for entry in spent.iter() {
+            if !block
+                .transactions
+                .iter()
+                .any(|txn| txn.hash == entry.txn_hash)
+            {
+                self.repo.save_utxo(entry).unwrap();
+            }
+        }
+        self.repo.remove_active_hash(block.height).unwrap();
+        match self.repo.get_block(block.prev_hash.clone()) {
+            Some(prev_block) => self.update_snapshot(&prev_block),
+            None => self.snapshot = BlockChainSnapshot::new(),
+        }
+        self.repo.save_snapshot(&self.snapshot).unwrap();
+        let now = Utc::now().timestamp();
+        for txn in block.transactions.iter().skip(1) {
+            let _ = self.accept_to_mempool(txn.clone(), now, self.snapshot.height);
+        }
+        self.save_mempool();
+        Ok(block)
+    }
+
+    fn clear_mempool(&mut self, block: &Block) {
+        let confirmed: Vec<(u64, u64)> = block
+            .transactions
+            .iter()
+            .filter_map(|txn| self.mempool.entry(&txn.hash))
+            .map(|entry| (entry.txn.fee_rate(), entry.height))
+            .collect();
+        self.fee_estimator.record_block(block.height, &confirmed);
+        self.repo.save_fee_estimator(&self.fee_estimator).unwrap();
+        for txn in block.transactions.iter() {
+            self.mempool.remove(&txn.hash);
+        }
+        self.mempool.expire(Utc::now().timestamp());
+        self.save_mempool();
+    }
+
+    // Fee rate a transaction should pay to be confirmed within `target_blocks`,
+    // which is never below what the mempool accepts
+    pub fn estimate_fee(&self, target_blocks: u64) -> u64 {
+        let pending: Vec<(u64, u64)> = self
+            .mempool
+            .entries()
+            .iter()
+            .map(|entry| {
+                let blocks_waited = self.snapshot.height.saturating_sub(entry.height);
+                (entry.txn.fee_rate(), blocks_waited)
+            })
+            .collect();
+        self.fee_estimator
+            .estimate(target_blocks, &pending)
+            .unwrap_or(0)
+            .max(self.policy.min_relay_fee_rate)
+    }
+
+    pub fn block_template(&self) -> Vec<Transaction> {
+        self.mempool
+            .block_template(MAX_BLOCK_SIZE - BLOCK_RESERVED_SIZE)
     }
 
     fn update_snapshot(&mut self, block: &Block) {
@@ -98,10 +431,207 @@ impl BlockChain {
         self.snapshot.difficulty = block.difficulty;
     }
 
-    pub fn add_block(&mut self, block: Block) {
-        self.update_snapshot(&block);
-        self.repo.save_block(&block).unwrap();
-        self.repo.save_snapshot(&self.snapshot).unwrap();
+    // Accepts the block along with the orphans building on it, or keeps it
+    // as an orphan of `peer` if its parent is unknown
+    pub fn add_block(&mut self, block: Block, peer: &str) -> Result<(), Error> {
+        self.orphans.expire(Utc::now().timestamp());
+        if !self.has_parent(&block) {
+            if block.difficulty < self.min_orphan_difficulty(block.height) {
+                return Err(Error::new("Orphan difficulty is too low"));
+            }
+            if !block.has_valid_proof() {
+                return Err(Error::new("Invalid proof of work"));
+            }
+            self.orphans.insert(block, peer, Utc::now().timestamp());
+            return Ok(());
+        }
+        let mut parents = vec![block.hash.clone()];
+        self.accept_block(block)?;
+        while let Some(parent) = parents.pop() {
+            for orphan in self.orphans.take_children(&parent) {
+                let hash = orphan.hash.clone();
+                if self.accept_block(orphan).is_ok() {
+                    parents.push(hash);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Lowest difficulty a block at `height` past the tip can have, as the difficulty
+    // falls by one per interval at most, so orphans can't be mined cheaply
+    fn min_orphan_difficulty(&self, height: u64) -> u16 {
+        let adjustments = (height.saturating_sub(1) / DIFFICULTY_INTERVAL)
+            .saturating_sub(self.snapshot.height / DIFFICULTY_INTERVAL);
+        self.calc_difficulty()
+            .saturating_sub(u16::try_from(adjustments).unwrap_or(u16::MAX))
+    }
+
+    fn has_parent(&self, block: &Block) -> bool {
+        block.prev_hash.is_empty() || self.repo.get_block(block.prev_hash.clone()).is_some()
+    }
+
+    // Whether the block is connected, so fully validated, unlike orphans and
+    // blocks of side branches
+    pub fn is_active_block(&self, hash: &str) -> bool {
+        self.repo
+            .get_block(hash.to_string())
+            .is_some_and(|block| self.repo.get_active_hash(block.height).as_deref() == Some(hash))
+    }
+
+    // Hash of the block missing for the orphan to connect, if it's an orphan
+    pub fn missing_ancestor(&self, hash: &str) -> Option<String> {
+        let mut orphan = self.orphans.get(hash)?;
+        while let Some(parent) = self.orphans.get(&orphan.prev_hash) {
+            orphan = parent;
+        }
+        Some(orphan.prev_hash.clone())
+    }
+
+    // Connects the block if it extends the chain, or keeps it on its side branch,
+    // switching to the branch once it has more work
+    fn accept_block(&mut self, block: Block) -> Result<(), Error> {
+        if self.invalid_blocks.contains(&block.hash) {
+            return Err(Error::new("Block is marked invalid"));
+        }
+        if block.prev_hash == self.snapshot.newest_hash {
+            self.validate_block(&block)?;
+            self.connect_block(&block)?;
+            self.clear_mempool(&block);
+            return Ok(());
+        }
+        self.check_side_block(&block)?;
+        self.store_block(&block);
+        self.activate_best_chain(Some(&block.hash))
+    }
+
+    // Checks which don't depend on the chain the block is on; the rest are done
+    // when the node switches to its branch
+    fn check_side_block(&self, block: &Block) -> Result<(), Error> {
+        let parent_height = match self.repo.get_block(block.prev_hash.clone()) {
+            Some(parent) => Some(parent.height),
+            None if block.prev_hash.is_empty() => Some(0),
+            None => None,
+        };
+        if parent_height.map(|height| height + 1) != Some(block.height) {
+            return Err(Error::new("Block does not extend the chain"));
+        }
+        if self.repo.get_block(block.hash.clone()).is_some() {
+            return Err(Error::new("Block is already known"));
+        }
+        if !self.params.matches_checkpoint(block.height, &block.hash) {
+            return Err(Error::new("Block does not match checkpoint"));
+        }
+        if block.size() > MAX_BLOCK_SIZE {
+            return Err(Error::new("Block is too large"));
+        }
+        if !block.has_valid_proof() {
+            return Err(Error::new("Invalid proof of work"));
+        }
+        self.check_coinbase(block)
+    }
+
+    fn check_extends_chain(&self, block: &Block) -> Result<(), Error> {
+        if block.prev_hash != self.snapshot.newest_hash || block.height != self.snapshot.height + 1
+        {
+            return Err(Error::new("Block does not extend the chain"));
+        }
+        if !self.params.matches_checkpoint(block.height, &block.hash) {
+            return Err(Error::new("Block does not match checkpoint"));
+        }
+        Ok(())
+    }
+
+    fn validate_block(&self, block: &Block) -> Result<(), Error> {
+        self.check_extends_chain(block)?;
+        // Checkpoints vouch for the transactions of the blocks they build on
+        if self.assumed_valid.contains(&block.hash) {
+            if !block.has_valid_proof() {
+                return Err(Error::new("Invalid proof of work"));
+            }
+            if block.difficulty != self.calc_difficulty() {
+                return Err(Error::new("Invalid difficulty"));
+            }
+            self.check_coinbase(block)?;
+            return self.check_coinbase_amount(block);
+        }
+        if block.size() > MAX_BLOCK_SIZE {
+            return Err(Error::new("Block is too large"));
+        }
+        if block.timestamp <= self.median_time_past() {
+            return Err(Error::new(
+                "Block timestamp is not after the median time past",
+            ));
+        }
+        if block.timestamp > self.adjusted_time() + MAX_FUTURE_BLOCK_TIME {
+            return Err(Error::new("Block timestamp is too far in the future"));
+        }
+        if !block.has_valid_proof() {
+            return Err(Error::new("Invalid proof of work"));
+        }
+        if block.difficulty != self.calc_difficulty() {
+            return Err(Error::new("Invalid difficulty"));
+        }
+        self.check_coinbase(block)?;
+        if self.has_unspent_outputs(block) {
+            return Err(Error::new("Transaction outputs already exist"));
+        }
+        let median_time = self.median_time_past();
+        for (idx, txn) in block.transactions.iter().enumerate().skip(1) {
+            // Transactions can spend from the ones before them in the block
+            let pending = &block.transactions[1..idx];
+            if !self.validate_transaction(txn, pending) {
+                return Err(Error::new("Invalid transaction"));
+            }
+            self.check_locks(txn, pending, block.height, median_time)?;
+        }
+        self.check_coinbase_amount(block)
+    }
+
+    fn check_coinbase(&self, block: &Block) -> Result<(), Error> {
+        match block.transactions.first() {
+            Some(txn) if txn.is_coinbase() => {}
+            _ => return Err(Error::new("Block must start with a coinbase transaction")),
+        }
+        if block.transactions[0].hash != block.transactions[0].hash() {
+            return Err(Error::new("Invalid coinbase transaction"));
+        }
+        Ok(())
+    }
+
+    fn check_coinbase_amount(&self, block: &Block) -> Result<(), Error> {
+        let fees: u64 = block.transactions.iter().skip(1).map(|txn| txn.fee()).sum();
+        let coinbase_amount: u64 = block.transactions[0]
+            .txn_outs
+            .iter()
+            .map(|txn_out| txn_out.amount)
+            .sum();
+        if coinbase_amount > MINER_REWARD + fees {
+            return Err(Error::new("Coinbase pays more than the reward and fees"));
+        }
+        Ok(())
+    }
+
+    // Median timestamp of the recent blocks, which unlike the newest timestamp
+    // cannot be skewed by a single miner
+    pub fn median_time_past(&self) -> i64 {
+        self.median_time_past_at(&self.snapshot.newest_hash)
+    }
+
+    // Median time past of the chain ending at the block `hash`
+    fn median_time_past_at(&self, hash: &str) -> i64 {
+        let mut timestamps = vec![];
+        let mut hash_cursor = hash.to_string();
+        while hash_cursor.as_str() != "" && timestamps.len() < MEDIAN_TIME_SPAN {
+            let block = self.repo.get_block(hash_cursor).unwrap();
+            timestamps.push(block.timestamp);
+            hash_cursor = block.prev_hash;
+        }
+        if timestamps.is_empty() {
+            return 0;
+        }
+        timestamps.sort_unstable();
+        timestamps[timestamps.len() / 2]
     }
 
     pub fn all_blocks(&self) -> Vec<Block> {
@@ -123,19 +653,177 @@ impl BlockChain {
         self.repo.get_block(hash)
     }
 
-    fn calc_difficulty(&mut self) -> u16 {
-        if self.snapshot.height != 0 && self.snapshot.height % DIFFICULTY_INTERVAL == 0 {
-            let all_blocks = self.all_blocks();
-            let newest_timestamp = all_blocks[0].timestamp;
-            let base_timestamp = all_blocks[(DIFFICULTY_INTERVAL - 1) as usize].timestamp;
-            let time_taken = newest_timestamp - base_timestamp;
+    // Whether the block is stored or waiting for its parent
+    pub fn has_block(&self, hash: &str) -> bool {
+        self.orphans.get(hash).is_some() || self.repo.get_block(hash.to_string()).is_some()
+    }
+
+    fn calc_difficulty(&self) -> u16 {
+        self.next_difficulty(&self.snapshot.newest_hash)
+    }
+
+    // Difficulty of the block after the block or header `prev_hash`
+    fn next_difficulty(&self, prev_hash: &str) -> u16 {
+        let prev = match self.header(prev_hash) {
+            Some(prev) => prev,
+            None => return BlockChainSnapshot::new().difficulty,
+        };
+        if prev.height.is_multiple_of(DIFFICULTY_INTERVAL) {
+            let mut base = prev.clone();
+            for _ in 1..DIFFICULTY_INTERVAL {
+                base = self.header(&base.prev_hash).unwrap();
+            }
+            let time_taken = prev.timestamp - base.timestamp;
             if time_taken < TIME_THRESHOLD - ALLOWED_BUFFER {
-                return self.snapshot.difficulty + 1;
+                return prev.difficulty + 1;
             } else if time_taken > TIME_THRESHOLD + ALLOWED_BUFFER {
-                return self.snapshot.difficulty - 1;
+                return prev.difficulty - 1;
+            }
+        }
+        prev.difficulty
+    }
+
+    // Header of a block, whether it's stored or only its header is known
+    fn header(&self, hash: &str) -> Option<BlockHeader> {
+        self.sync.get(hash).cloned().or_else(|| {
+            self.repo
+                .get_block(hash.to_string())
+                .map(|block| block.header())
+        })
+    }
+
+    // Validates headers, oldest first, ahead of downloading their blocks
+    pub fn add_headers(&mut self, headers: &[BlockHeader]) -> Result<(), Error> {
+        for header in headers.iter() {
+            if self.header(&header.hash).is_some() {
+                continue;
             }
+            if self.invalid_blocks.contains(&header.hash)
+                || self.invalid_blocks.contains(&header.prev_hash)
+            {
+                return Err(Error::new("Header is marked invalid"));
+            }
+            let prev_height = match self.header(&header.prev_hash) {
+                Some(prev) => Some(prev.height),
+                None if header.prev_hash.is_empty() => Some(0),
+                None => None,
+            };
+            if prev_height.map(|height| height + 1) != Some(header.height) {
+                return Err(Error::new("Header does not extend a known header"));
+            }
+            if !self.params.matches_checkpoint(header.height, &header.hash) {
+                return Err(Error::new("Header does not match checkpoint"));
+            }
+            if !header.has_valid_proof() {
+                return Err(Error::new("Invalid proof of work"));
+            }
+            if header.difficulty != self.next_difficulty(&header.prev_hash) {
+                return Err(Error::new("Invalid difficulty"));
+            }
+            let prev_work = self
+                .sync
+                .chain_work(&header.prev_hash)
+                .unwrap_or_else(|| self.chain_work(&header.prev_hash));
+            let chain_work = prev_work.saturating_add(header.work());
+            if !self.sync.insert(header.clone(), chain_work) {
+                return Err(Error::new("Too many headers pending"));
+            }
+        }
+        self.update_assumed_valid();
+        Ok(())
+    }
+
+    // Whether neither the headers nor the blocks up to the header are marked invalid
+    fn is_header_valid(&self, hash: &str) -> bool {
+        let mut hash_cursor = hash.to_string();
+        while let Some(header) = self.sync.get(&hash_cursor) {
+            if self.invalid_blocks.contains(&header.hash) {
+                return false;
+            }
+            hash_cursor = header.prev_hash.clone();
+        }
+        self.branch_hashes(&hash_cursor)
+            .is_disjoint(&self.invalid_blocks)
+    }
+
+    // Tip of the header chain with the most work, if it has more than the chain
+    fn best_header(&self) -> Option<BlockHeader> {
+        let active_work = self.chain_work(&self.snapshot.newest_hash);
+        self.sync
+            .by_work()
+            .into_iter()
+            .filter(|header| self.sync.chain_work(&header.hash) > Some(active_work))
+            .find(|header| self.is_header_valid(&header.hash))
+            .cloned()
+    }
+
+    // Blocks of the best header chain the peer should send next, oldest first
+    pub fn blocks_to_download(&mut self, peer: &str, now: i64) -> Vec<String> {
+        let mut missing = vec![];
+        let mut hash_cursor = match self.best_header() {
+            Some(best) => best.hash,
+            None => return missing,
+        };
+        while let Some(header) = self.sync.get(&hash_cursor) {
+            if self.orphans.get(&header.hash).is_none() {
+                missing.push(header.hash.clone());
+            }
+            hash_cursor = header.prev_hash.clone();
+        }
+        missing.reverse();
+        self.sync.assign(&missing, peer, now)
+    }
+
+    // Hashes of the chain from the newest block back, one by one for the most recent
+    // ones and then exponentially spaced, ending with the first block
+    pub fn block_locator(&self) -> Vec<String> {
+        let mut locator = vec![];
+        let mut step = 1;
+        let mut next_height = self.snapshot.height;
+        let mut hash_cursor = self.snapshot.newest_hash.clone();
+        while let Some(block) = self.repo.get_block(hash_cursor) {
+            if block.height == next_height || block.height == 1 {
+                locator.push(block.hash.clone());
+            }
+            if block.height == next_height {
+                if locator.len() >= LOCATOR_DENSE_HASHES {
+                    step *= 2;
+                }
+                next_height = next_height.saturating_sub(step);
+            }
+            hash_cursor = block.prev_hash;
+        }
+        locator
+    }
+
+    // Headers of the chain after where it forks off the chain of the `locator`,
+    // or from the first block if they share none, oldest first
+    pub fn headers_after(&self, locator: &[String], max: usize) -> Vec<BlockHeader> {
+        let fork_height = locator
+            .iter()
+            .filter_map(|hash| self.repo.get_block(hash.clone()))
+            .find(|block| self.repo.get_active_hash(block.height).as_ref() == Some(&block.hash))
+            .map_or(0, |block| block.height);
+        (fork_height + 1..=self.snapshot.height)
+            .take(max)
+            .map_while(|height| self.repo.get_active_hash(height))
+            .map_while(|hash| self.repo.get_block(hash))
+            .map(|block| block.header())
+            .collect()
+    }
+
+    pub fn sync_status(&self) -> SyncStatus {
+        let best = self.best_header();
+        let header_height = best
+            .as_ref()
+            .map_or(self.snapshot.height, |best| best.height);
+        SyncStatus {
+            header_height,
+            block_height: self.snapshot.height,
+            best_header_hash: best.map_or(self.snapshot.newest_hash.clone(), |best| best.hash),
+            blocks_in_flight: self.sync.in_flight(Utc::now().timestamp()),
+            is_synced: header_height <= self.snapshot.height,
         }
-        return self.snapshot.difficulty;
     }
 
     pub fn all_txn_outs(&self) -> Vec<TxnOut> {
@@ -157,70 +845,254 @@ impl BlockChain {
             .sum()
     }
 
-    fn is_on_mempool(&self, utxnout: &UTxnOut) -> bool {
-        self.snapshot.mempool.iter().any(|txn| {
-            txn.txn_ins
+    pub fn unspent_txnouts_by_address(&self, address: &str) -> Vec<UTxnOut> {
+        let script_pubkey = Script::for_address(address);
+        let confirmed = self
+            .repo
+            .get_utxos_by_script(&script_pubkey)
+            .into_iter()
+            .map(|entry| UTxnOut::new(&entry.txn_hash, entry.idx, entry.txn_out.amount));
+        // Outputs of unconfirmed transactions can be spent as well
+        let unconfirmed = self.mempool.iter().flat_map(|txn| {
+            txn.txn_outs
                 .iter()
-                .any(|txn_in| txn_in.txn_hash == utxnout.txn_hash && txn_in.idx == utxnout.idx)
-        })
+                .enumerate()
+                .filter(|(_, txn_out)| txn_out.script_pubkey == script_pubkey)
+                .map(|(idx, txn_out)| UTxnOut::new(&txn.hash, idx as i64, txn_out.amount))
+        });
+        confirmed
+            .chain(unconfirmed)
+            .filter(|utxnout| {
+                self.mempool
+                    .spender(&utxnout.txn_hash, utxnout.idx)
+                    .is_none()
+            })
+            .collect()
     }
 
-    pub fn unspent_txnouts_by_address(&self, address: &str) -> Vec<UTxnOut> {
-        let mut utxnouts = vec![];
-        let mut existing_txn_hashes: HashSet<&str> = HashSet::new();
-        for block in self.all_blocks().iter() {
-            for txn in block.transactions.iter() {
-                for txn_in in txn.txn_ins.iter() {
-                    if txn_in.signature.as_str() == "COINBASE" {
-                        break;
-                    }
-                    if txn.txn_outs[txn_in.idx as usize].address == address {
-                        existing_txn_hashes.insert(txn_in.txn_hash.as_str());
+    fn get_transaction(&self, id: &str) -> Option<Transaction> {
+        self.get_transaction_block(id)
+            .and_then(|block| block.transactions.into_iter().find(|txn| txn.hash == id))
+    }
+
+    fn get_transaction_block(&self, id: &str) -> Option<Block> {
+        self.repo
+            .get_txn_block(id)
+            .and_then(|block_hash| self.repo.get_block(block_hash))
+    }
+
+    // Checks lock times against the block the transaction would be mined in.
+    // `pending` holds the unconfirmed transactions `txn` may spend from
+    fn check_locks(
+        &self,
+        txn: &Transaction,
+        pending: &[Transaction],
+        height: u64,
+        median_time: i64,
+    ) -> Result<(), Error> {
+        if !txn.is_final(height, median_time) {
+            return Err(Error::new("Transaction is time-locked"));
+        }
+        for txn_in in txn.txn_ins.iter() {
+            // Confirmed outputs come with the height of the block which confirmed them
+            let (txn_out, confirmed_height) = match self.repo.get_utxo(&txn_in.txn_hash, txn_in.idx)
+            {
+                Some(entry) => (entry.txn_out, Some(entry.height)),
+                None => {
+                    let txn_out = pending
+                        .iter()
+                        .find(|prev_txn| prev_txn.hash == txn_in.txn_hash)
+                        .and_then(|prev_txn| prev_txn.txn_outs.get(txn_in.idx as usize).cloned());
+                    match txn_out {
+                        Some(txn_out) => (txn_out, None),
+                        None => continue,
                     }
                 }
-                for (idx, txn_out) in txn.txn_outs.clone().into_iter().enumerate() {
-                    if txn_out.address.as_str() == address
-                        && !existing_txn_hashes.contains(txn.hash.as_str())
-                    {
-                        let utxnout =
-                            UTxnOut::new(&txn.hash, idx.try_into().unwrap(), txn_out.amount);
-                        if !self.is_on_mempool(&utxnout) {
-                            utxnouts.push(utxnout);
-                        }
-                    }
+            };
+            // Unconfirmed outputs haven't started counting their relative locks,
+            // which count time from the median time past before the confirming block
+            if let Some(relative_lock) = &txn_out.relative_lock {
+                let satisfied = confirmed_height.is_some_and(|confirmed_height| {
+                    let prev_hash = self
+                        .repo
+                        .get_active_hash(confirmed_height.saturating_sub(1))
+                        .unwrap_or_default();
+                    let confirmed_time = self.median_time_past_at(&prev_hash);
+                    relative_lock.is_satisfied(
+                        confirmed_height,
+                        confirmed_time,
+                        height,
+                        median_time,
+                    )
+                });
+                if !satisfied {
+                    return Err(Error::new("Output is time-locked"));
+                }
+            }
+            // Scripts can only check that refunds come after the timeout,
+            // so claims are kept from coming after it here
+            if let Some(timeout) = txn_out.script_pubkey.htlc_timeout() {
+                if is_htlc_claim(&txn_in.script_sig)
+                    && Htlc::is_expired(timeout, height, median_time)
+                {
+                    return Err(Error::new("HTLC has expired"));
                 }
             }
         }
-        utxnouts
+        Ok(())
     }
 
-    fn get_transaction(&self, id: &str) -> Option<Transaction> {
-        let blocks = self.all_blocks();
-        for block in blocks.iter() {
-            for txn in block.transactions.iter() {
-                if txn.hash == id {
-                    return Some(txn.clone());
-                }
+    fn get_txn_out(&self, txn_hash: &str, idx: i64) -> Option<TxnOut> {
+        self.get_transaction(txn_hash)
+            .and_then(|txn| txn.txn_outs.get(idx as usize).cloned())
+    }
+
+    fn htlc_spend(
+        &self,
+        txn_hash: &str,
+        idx: i64,
+        wallet: &Wallet,
+    ) -> Result<(Transaction, u64), Error> {
+        let txn_out = self
+            .get_txn_out(txn_hash, idx)
+            .ok_or_else(|| Error::new("HTLC not found"))?;
+        let timeout = txn_out
+            .script_pubkey
+            .htlc_timeout()
+            .ok_or_else(|| Error::new("HTLC not found"))?;
+        let txn = Transaction::new(
+            vec![TxnIn::new(txn_hash, idx, txn_out.amount)],
+            vec![TxnOut::new(&wallet.address, txn_out.amount)],
+        );
+        Ok((txn, timeout))
+    }
+
+    pub fn claim_htlc(
+        &mut self,
+        txn_hash: &str,
+        idx: i64,
+        preimage: &[u8],
+        wallet: &Wallet,
+    ) -> Result<Transaction, Error> {
+        let (mut txn, _) = self.htlc_spend(txn_hash, idx, wallet)?;
+        txn.txn_ins[0].script_sig = Script::new(vec![
+            Op::Push(txn.signature(wallet)),
+            Op::Push(wallet.public_key()),
+            Op::Push(preimage.to_vec()),
+            Op::Push(vec![1]),
+        ]);
+        self.submit_transaction(txn.clone())?;
+        Ok(txn)
+    }
+
+    pub fn refund_htlc(
+        &mut self,
+        txn_hash: &str,
+        idx: i64,
+        wallet: &Wallet,
+    ) -> Result<Transaction, Error> {
+        let (mut txn, timeout) = self.htlc_spend(txn_hash, idx, wallet)?;
+        txn.set_lock_time(timeout);
+        txn.txn_ins[0].script_sig = Script::new(vec![
+            Op::Push(txn.signature(wallet)),
+            Op::Push(wallet.public_key()),
+            Op::Push(vec![]),
+        ]);
+        self.submit_transaction(txn.clone())?;
+        Ok(txn)
+    }
+
+    // Preimage revealed by a confirmed transaction claiming an HTLC
+    pub fn htlc_preimage(&self, claim_txn_hash: &str) -> Option<Vec<u8>> {
+        let claim_txn = self.get_transaction(claim_txn_hash)?;
+        claim_txn.txn_ins.iter().find_map(|txn_in| {
+            self.get_txn_out(&txn_in.txn_hash, txn_in.idx)?
+                .script_pubkey
+                .htlc_timeout()?;
+            if !is_htlc_claim(&txn_in.script_sig) {
+                return None;
             }
-        }
-        None
+            let pushed_data = txn_in.script_sig.pushed_data();
+            Some(pushed_data[pushed_data.len() - 2].to_vec())
+        })
     }
 
-    fn validate_transaction(&self, txn: &Transaction) -> bool {
+    // Finds the block anchoring `payload` in a data output
+    pub fn find_data_block(&self, payload: &[u8]) -> Option<Block> {
+        self.repo
+            .get_data_txns(payload)
+            .iter()
+            .rev()
+            .find_map(|txn_hash| self.repo.get_txn_block(txn_hash))
+            .and_then(|block_hash| self.repo.get_block(block_hash))
+    }
+
+    // `pending` holds the unconfirmed transactions `txn` may spend from
+    fn validate_transaction(&self, txn: &Transaction, pending: &[Transaction]) -> bool {
+        if txn.hash != txn.hash() || txn.txn_ins.is_empty() {
+            return false;
+        }
+        if !txn.txn_outs.iter().all(TxnOut::is_valid) {
+            return false;
+        }
+        let mut total_in = 0;
+        let mut spent: HashSet<(&str, i64)> = HashSet::new();
         for txn_in in txn.txn_ins.iter() {
-            match self.get_transaction(txn_in.txn_hash.as_str()) {
-                Some(prev_txn) => {
-                    let address = prev_txn.txn_outs[txn_in.idx as usize].address.as_str();
-                    let signature = txn_in.signature.as_str();
-                    let msg = hex::encode(&txn.hash);
-                    if !verify_msg(address, msg.as_str(), signature) {
+            let outpoint = (txn_in.txn_hash.as_str(), txn_in.idx);
+            let spent_by_pending = pending.iter().any(|prev_txn| {
+                prev_txn
+                    .txn_ins
+                    .iter()
+                    .any(|prev_txn_in| (prev_txn_in.txn_hash.as_str(), prev_txn_in.idx) == outpoint)
+            });
+            if !spent.insert(outpoint) || spent_by_pending {
+                return false;
+            }
+            let prev_txn_out = match self.repo.get_utxo(&txn_in.txn_hash, txn_in.idx) {
+                Some(entry) => Some(entry.txn_out),
+                None => pending
+                    .iter()
+                    .find(|prev_txn| prev_txn.hash == txn_in.txn_hash)
+                    .and_then(|prev_txn| prev_txn.txn_outs.get(txn_in.idx as usize).cloned()),
+            };
+            match prev_txn_out {
+                Some(prev_txn_out) => {
+                    if prev_txn_out.amount != txn_in.amount {
+                        return false;
+                    }
+                    if verify_script(&txn_in.script_sig, &prev_txn_out.script_pubkey, txn).is_err()
+                    {
                         return false;
                     }
+                    total_in += txn_in.amount;
                 }
                 None => return false,
             }
         }
-        true
+        let total_out: u64 = txn.txn_outs.iter().map(|txn_out| txn_out.amount).sum();
+        total_in >= total_out
+    }
+
+    // Funds the outputs of `builder` from `from`, preferring a changeless exact match
+    // and falling back to spending the largest outputs. Unless the builder sets a fee rate,
+    // it pays the estimated one
+    pub fn build_transaction(
+        &self,
+        from: &str,
+        builder: TransactionBuilder,
+    ) -> Result<Transaction, Error> {
+        if self.balance_by_address(from) < builder.payment() {
+            return Err(Error::new("Not enough balance"));
+        }
+        let builder = builder
+            .inputs(self.unspent_txnouts_by_address(from))
+            .change_address(from)
+            .default_fee_rate(self.estimate_fee(DEFAULT_TARGET_BLOCKS));
+        builder
+            .clone()
+            .coin_selector(Arc::new(BranchAndBound))
+            .build()
+            .or_else(|_| builder.coin_selector(Arc::new(LargestFirst)).build())
     }
 
     pub fn make_transaction(
@@ -230,56 +1102,326 @@ impl BlockChain {
         amount: u64,
         wallet: &Wallet,
     ) -> Result<Transaction, Error> {
-        if self.balance_by_address(from) < amount {
-            Err(Error::new("Not enough balance"))
-        } else {
-            let utxn_outs = self.unspent_txnouts_by_address(from);
-            let mut txn_ins: Vec<TxnIn> = vec![];
-            let mut txn_outs: Vec<TxnOut> = vec![];
-            let mut total = 0;
-            for utxnout in utxn_outs.into_iter() {
-                if total >= amount {
-                    break;
-                }
-                txn_ins.push(TxnIn::new(&utxnout.txn_hash, utxnout.idx, utxnout.amount));
-                total += utxnout.amount;
-            }
-            // Bring changes back to transaction sender
-            if total > amount {
-                txn_outs.push(TxnOut::new(from, total - amount));
-            }
-            txn_outs.push(TxnOut::new(to, amount));
-            let mut transaction = Transaction::new(txn_ins, txn_outs);
-            transaction.sign(wallet);
-            if !self.validate_transaction(&transaction) {
-                return Err(Error::new("Invalid transaction"));
-            };
-            self.add_txn_to_mempool(transaction.clone());
-            Ok(transaction)
-        }
-    }
-
-    pub fn add_txn_to_mempool(&mut self, txn: Transaction) {
-        self.snapshot.mempool.push(txn);
+        let mut transaction =
+            self.build_transaction(from, TransactionBuilder::new().output(to, amount))?;
+        transaction.sign(wallet);
+        self.submit_transaction(transaction.clone())?;
+        Ok(transaction)
     }
 
-    pub fn replace(&mut self, new_blocks: Vec<Block>) {
-        self.update_snapshot(&new_blocks[0]);
-        self.repo.save_snapshot(&self.snapshot).unwrap();
-        let _ = self.repo.remove_all_blocks();
-        for block in new_blocks.iter() {
-            self.repo.save_block(block).unwrap();
+    // Rebuilds a replaceable mempool transaction of `wallet` at a higher fee rate,
+    // paying the extra fee out of its change
+    pub fn bump_fee(
+        &mut self,
+        txn_hash: &str,
+        fee_rate: u64,
+        wallet: &Wallet,
+    ) -> Result<Transaction, Error> {
+        let txn = self
+            .mempool
+            .get(txn_hash)
+            .cloned()
+            .ok_or_else(|| Error::new("Transaction not found in mempool"))?;
+        if !txn.replaceable {
+            return Err(Error::new("Transaction is not replaceable"));
+        }
+        let fee = fee_for_size(txn.size(), fee_rate);
+        if fee <= txn.fee() {
+            return Err(Error::new("Fee rate must be higher"));
+        }
+        let mut txn_outs = txn.txn_outs.clone();
+        let change = txn_outs
+            .iter_mut()
+            .find(|txn_out| txn_out.is_locked_to(&wallet.address))
+            .filter(|txn_out| txn_out.amount >= fee - txn.fee())
+            .ok_or_else(|| Error::new("Not enough change to bump fee"))?;
+        change.amount -= fee - txn.fee();
+        let mut bumped = Transaction::new(txn.txn_ins.clone(), txn_outs);
+        bumped.set_lock_time(txn.lock_time);
+        bumped.set_replaceable(true);
+        bumped.sign(wallet);
+        self.submit_transaction(bumped.clone())?;
+        Ok(bumped)
+    }
+
+    pub fn submit_transaction(&mut self, txn: Transaction) -> Result<(), Error> {
+        let result = self.accept_to_mempool(txn, Utc::now().timestamp(), self.snapshot.height);
+        self.save_mempool();
+        result
+    }
+
+    // Adds `txn` to the mempool as if it arrived at `time`, on top of the block at `height`
+    fn accept_to_mempool(&mut self, txn: Transaction, time: i64, height: u64) -> Result<(), Error> {
+        self.mempool.expire(Utc::now().timestamp());
+        self.policy.check(&txn)?;
+        let parents = self.mempool_parents(&txn);
+        if !self.validate_transaction(&txn, &parents) {
+            return Err(Error::new("Invalid transaction"));
+        };
+        self.check_locks(
+            &txn,
+            &parents,
+            self.snapshot.height + 1,
+            self.median_time_past(),
+        )?;
+        if self
+            .mempool
+            .count_ancestors(&txn, MAX_UNCONFIRMED_ANCESTORS)
+            >= MAX_UNCONFIRMED_ANCESTORS
+        {
+            return Err(Error::new("Too many unconfirmed ancestors"));
+        }
+        let conflicts = self.mempool.conflicts(&txn);
+        check_replacement(&txn, &conflicts)?;
+        let replaced: Vec<MempoolEntry> = conflicts
+            .iter()
+            .filter_map(|conflict| self.mempool.entry(&conflict.hash).cloned())
+            .collect();
+        for conflict in conflicts.iter() {
+            self.mempool.remove(&conflict.hash);
+        }
+        self.mempool.insert(txn.clone(), time, height);
+        let evicted = self.mempool.trim();
+        if evicted.iter().any(|entry| entry.txn == txn) {
+            // What the transaction replaced or pushed out fit before it came in
+            for entry in replaced
+                .into_iter()
+                .chain(evicted.into_iter().filter(|entry| entry.txn != txn))
+            {
+                self.mempool.insert(entry.txn, entry.time, entry.height);
+            }
+            return Err(Error::new("Mempool is full"));
+        }
+        Ok(())
+    }
+
+    // Unconfirmed transactions `txn` spends from
+    fn mempool_parents(&self, txn: &Transaction) -> Vec<Transaction> {
+        txn.txn_ins
+            .iter()
+            .filter_map(|txn_in| self.mempool.get(&txn_in.txn_hash).cloned())
+            .collect()
+    }
+
+    // Rolls the chain back to the parent of the block, if it's on it, and keeps
+    // the node off the branches building on it until it's reconsidered
+    pub fn invalidate_block(&mut self, hash: &str) -> Result<(), Error> {
+        let block = self
+            .repo
+            .get_block(hash.to_string())
+            .ok_or_else(|| Error::new("Block not found"))?;
+        self.mark_invalid(&block.hash);
+        while self.active_hashes().contains(&block.hash) {
+            self.disconnect_tip()?;
+        }
+        self.activate_best_chain(None)
+    }
+
+    // Clears the marks of the block, its ancestors and descendants, and switches
+    // to the best branch again
+    pub fn reconsider_block(&mut self, hash: &str) -> Result<(), Error> {
+        let block = self
+            .repo
+            .get_block(hash.to_string())
+            .ok_or_else(|| Error::new("Block not found"))?;
+        let ancestors = self.branch_hashes(&block.hash);
+        let cleared: Vec<String> = self
+            .invalid_blocks
+            .iter()
+            .filter(|invalid| {
+                ancestors.contains(*invalid) || self.branch_hashes(invalid).contains(&block.hash)
+            })
+            .cloned()
+            .collect();
+        for invalid in cleared.iter() {
+            self.invalid_blocks.remove(invalid);
+        }
+        self.save_invalid_blocks();
+        self.activate_best_chain(None)
+    }
+
+    fn mark_invalid(&mut self, hash: &str) {
+        self.invalid_blocks.insert(hash.to_string());
+        self.save_invalid_blocks();
+    }
+
+    fn save_invalid_blocks(&self) {
+        let hashes: Vec<String> = self.invalid_blocks.iter().cloned().collect();
+        self.repo.save_invalid_blocks(&hashes).unwrap();
+    }
+
+    // Hashes of the block and its ancestors
+    fn branch_hashes(&self, hash: &str) -> HashSet<String> {
+        let mut hashes = HashSet::new();
+        let mut hash_cursor = hash.to_string();
+        while let Some(block) = self.repo.get_block(hash_cursor) {
+            hashes.insert(block.hash);
+            hash_cursor = block.prev_hash;
         }
+        hashes
+    }
+
+    fn active_hashes(&self) -> HashSet<String> {
+        self.branch_hashes(&self.snapshot.newest_hash)
+    }
+
+    fn chain_work(&self, hash: &str) -> u128 {
+        let mut work: u128 = 0;
+        let mut hash_cursor = hash.to_string();
+        while let Some(block) = self.repo.get_block(hash_cursor) {
+            work = work.saturating_add(block.work());
+            hash_cursor = block.prev_hash;
+        }
+        work
+    }
+
+    // Known branches, the active one first, then by the most work
+    pub fn chain_tips(&self) -> Vec<ChainTip> {
+        let active = self.active_hashes();
+        let mut hashes: Vec<&String> = self.tips.iter().collect();
+        if !self.tips.contains(&self.snapshot.newest_hash) && !active.is_empty() {
+            hashes.push(&self.snapshot.newest_hash);
+        }
+        let mut tips: Vec<ChainTip> = hashes
+            .into_iter()
+            .filter_map(|hash| self.repo.get_block(hash.clone()))
+            .map(|tip| {
+                let branch = self.branch_hashes(&tip.hash);
+                let status = if !branch.is_disjoint(&self.invalid_blocks) {
+                    TipStatus::Invalid
+                } else if tip.hash == self.snapshot.newest_hash {
+                    TipStatus::Active
+                } else {
+                    TipStatus::ValidFork
+                };
+                ChainTip {
+                    chain_work: self.chain_work(&tip.hash),
+                    branch_length: branch.difference(&active).count() as u64,
+                    hash: tip.hash,
+                    height: tip.height,
+                    status,
+                }
+            })
+            .collect();
+        for header in self.sync.tips() {
+            let mut branch_length = 0;
+            let mut hash_cursor = header.hash.clone();
+            while let Some(header) = self.sync.get(&hash_cursor) {
+                branch_length += 1;
+                hash_cursor = header.prev_hash.clone();
+            }
+            branch_length += self.branch_hashes(&hash_cursor).difference(&active).count() as u64;
+            tips.push(ChainTip {
+                hash: header.hash.clone(),
+                height: header.height,
+                chain_work: self.sync.chain_work(&header.hash).unwrap_or_default(),
+                branch_length,
+                status: if self.is_header_valid(&header.hash) {
+                    TipStatus::HeadersOnly
+                } else {
+                    TipStatus::Invalid
+                },
+            });
+        }
+        tips.sort_by_key(|tip| (tip.status != TipStatus::Active, Reverse(tip.chain_work)));
+        tips
+    }
+
+    // Switches to the branch with the most work without blocks marked invalid,
+    // marking the blocks which fail validation on the way
+    fn activate_best_chain(&mut self, added_hash: Option<&str>) -> Result<(), Error> {
+        // Error of the branch of the added block, which is rejected along with it
+        let mut rejected = None;
+        loop {
+            let best = self
+                .tips
+                .iter()
+                .filter(|tip| self.branch_hashes(tip).is_disjoint(&self.invalid_blocks))
+                .filter_map(|tip| self.repo.get_block(tip.clone()))
+                .max_by_key(|tip| self.chain_work(&tip.hash));
+            let active_work = self.chain_work(&self.snapshot.newest_hash);
+            match best {
+                Some(best) if self.chain_work(&best.hash) > active_work => {
+                    match self.switch_to(&best) {
+                        Ok(()) => {}
+                        Err(SwitchError::Invalid(invalid, err)) => {
+                            self.mark_invalid(&invalid);
+                            if added_hash
+                                .is_some_and(|hash| self.branch_hashes(hash).contains(&invalid))
+                            {
+                                rejected = Some(err);
+                            }
+                        }
+                        Err(SwitchError::Disconnect(err)) => return Err(err),
+                    }
+                }
+                _ => return rejected.map_or(Ok(()), Err),
+            }
+        }
+    }
+
+    // Disconnects the chain down to where the branch of `tip` forks off and connects
+    // the branch. The tip stays in place when the chain can't be rolled back
+    fn switch_to(&mut self, tip: &Block) -> Result<(), SwitchError> {
+        let mut branch = vec![];
+        let mut cursor = tip.clone();
+        let fork_hash = loop {
+            if self.repo.get_active_hash(cursor.height).as_ref() == Some(&cursor.hash) {
+                break cursor.hash;
+            }
+            let prev_hash = cursor.prev_hash.clone();
+            branch.push(cursor);
+            match self.repo.get_block(prev_hash) {
+                Some(prev_block) => cursor = prev_block,
+                None => break String::new(),
+            }
+        };
+        let mut hash_cursor = self.snapshot.newest_hash.clone();
+        while hash_cursor != fork_hash {
+            if self.repo.get_undo(&hash_cursor).is_none() {
+                return Err(SwitchError::Disconnect(Error::new("Undo data not found")));
+            }
+            match self.repo.get_block(hash_cursor) {
+                Some(block) => hash_cursor = block.prev_hash,
+                None => return Err(SwitchError::Disconnect(Error::new("Block not found"))),
+            }
+        }
+        while self.snapshot.newest_hash != fork_hash {
+            self.disconnect_tip().map_err(SwitchError::Disconnect)?;
+        }
+        for block in branch.iter().rev() {
+            if let Err(err) = self
+                .validate_block(block)
+                .and_then(|()| self.connect_block(block))
+            {
+                return Err(SwitchError::Invalid(block.hash.clone(), err));
+            }
+            self.clear_mempool(block);
+        }
+        Ok(())
     }
 
     pub fn mempool(&self) -> Vec<Transaction> {
-        self.snapshot.mempool.clone()
+        self.mempool.transactions()
+    }
+
+    pub fn mempool_transaction(&self, txn_hash: &str) -> Option<Transaction> {
+        self.mempool.get(txn_hash).cloned()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::testutils;
+    use crate::sync::MAX_HEADERS;
+    use sha2::{Digest, Sha256};
+    use std::collections::BTreeMap;
+
+    use crate::{
+        address::Network,
+        mempool::MempoolEntry,
+        testutils,
+        transaction::{MultiSig, RelativeLock, MAX_DATA_SIZE},
+    };
 
     use super::*;
 
@@ -296,21 +1438,33 @@ mod tests {
         // Given
         let test_repo = testutils::TestRepository::new();
         let mut test_snapshot = BlockChainSnapshot::new();
-        let block1 = Block::mine("some_address", "", 1, 1, &mut vec![]);
-        let block2 = Block::mine("some_address", block1.hash.as_str(), 2, 1, &mut vec![]);
+        let block1 = Block::mine("some_address", "", 1, 1, 100, &mut vec![]);
+        let block2 = Block::mine("some_address", block1.hash.as_str(), 2, 1, 200, &mut vec![]);
         test_snapshot.height = 2;
         test_snapshot.newest_hash = block2.hash.clone();
 
+        let fork2 = Block::mine(
+            "other_address",
+            block1.hash.as_str(),
+            2,
+            1,
+            200,
+            &mut vec![],
+        );
+
         test_repo.save_snapshot(&test_snapshot).unwrap();
         test_repo.save_block(&block1).unwrap();
         test_repo.save_block(&block2).unwrap();
+        test_repo.save_block(&fork2).unwrap();
 
         // When
         let chain = BlockChain::load(Box::new(test_repo));
 
         // Then
         assert_eq!(chain.snapshot.height, 2);
-        assert_eq!(chain.all_blocks(), vec![block2, block1]);
+        assert_eq!(chain.all_blocks(), vec![block2.clone(), block1]);
+        assert!(chain.has_block(&fork2.hash));
+        assert_eq!(chain.chain_tips()[0].hash, block2.hash);
     }
 
     #[test]
@@ -320,14 +1474,14 @@ mod tests {
 
         // When
         let mut chain = BlockChain::load(test_repo);
-        let block1 = chain.mine_block("some-address");
+        let block1 = chain.mine_block(&testutils::address("some"));
 
         // Then
         assert_eq!(chain.snapshot.height, 1);
         assert_eq!(chain.snapshot.newest_hash, block1.hash);
 
         // When
-        let block2 = chain.mine_block("some-address");
+        let block2 = chain.mine_block(&testutils::address("some"));
 
         // Then
         assert_eq!(chain.snapshot.height, 2);
@@ -344,10 +1498,10 @@ mod tests {
 
         // When
         let mut chain = BlockChain::load(test_repo);
-        let block = chain.mine_block("some-address");
+        let block = chain.mine_block(&testutils::address("some"));
 
         // Then
-        let balance = chain.balance_by_address("some-address");
+        let balance = chain.balance_by_address(&testutils::address("some"));
         assert_eq!(balance, block.transactions[0].txn_outs[0].amount);
     }
 
@@ -364,21 +1518,21 @@ mod tests {
 
         // When
         chain
-            .make_transaction(address, "to-address", 20, &wallet)
+            .make_transaction(address, &testutils::address("to"), 20, &wallet)
             .unwrap();
         chain
-            .make_transaction(address, "to-address", 10, &wallet)
+            .make_transaction(address, &testutils::address("to"), 10, &wallet)
             .unwrap();
 
         // Then
         let mempool = chain.mempool();
         let mem_txn = mempool[0].clone();
         assert_eq!(mempool.len(), 2);
-        assert_eq!(chain.balance_by_address(address), 50); // 100 were spent for making transaction
+        assert_eq!(chain.balance_by_address(address), 120); // 30 were paid, and the rest came back as change
         assert_eq!(mem_txn.txn_ins[0].amount, 50);
-        assert_eq!(mem_txn.txn_outs[0].address, String::from(address));
+        assert!(mem_txn.txn_outs[0].is_locked_to(address));
         assert_eq!(mem_txn.txn_outs[0].amount, 30);
-        assert_eq!(mem_txn.txn_outs[1].address, String::from("to-address"));
+        assert!(mem_txn.txn_outs[1].is_locked_to(&testutils::address("to")));
         assert_eq!(mem_txn.txn_outs[1].amount, 20);
     }
 
@@ -391,7 +1545,7 @@ mod tests {
         let address = wallet.address.as_str();
         chain.mine_block(address); // Earn 50 by mining block
         chain
-            .make_transaction(address, "to-address", 20, &wallet)
+            .make_transaction(address, &testutils::address("to"), 20, &wallet)
             .unwrap();
 
         // When
@@ -399,22 +1553,1403 @@ mod tests {
 
         // Then
         assert_eq!(chain.balance_by_address(address), 80);
-        assert_eq!(chain.balance_by_address("to-address"), 20);
+        assert_eq!(chain.balance_by_address(&testutils::address("to")), 20);
+        assert_eq!(chain.mempool().len(), 0);
+    }
+
+    #[test]
+    fn mining_block_confirms_transaction_without_change() {
+        // Given
+        let test_repo = Box::new(testutils::TestRepository::new());
+        let mut chain = BlockChain::load(test_repo);
+        let wallet = testutils::test_wallet();
+        let address = wallet.address.as_str();
+        chain.mine_block(address); // Earn 50 by mining block
+        chain.mine_block(address); // Earn 50 by mining block
+        let txn = chain
+            .make_transaction(address, &testutils::address("to"), 100, &wallet)
+            .unwrap();
+
+        // When
+        chain.mine_block(&testutils::address("miner"));
+
+        // Then
+        assert_eq!(
+            txn.txn_outs,
+            vec![TxnOut::new(&testutils::address("to"), 100)]
+        );
+        assert_eq!(chain.balance_by_address(address), 0);
+        assert_eq!(chain.balance_by_address(&testutils::address("to")), 100);
+    }
+
+    #[test]
+    fn cannot_submit_transaction_spending_more_than_its_inputs() {
+        // Given
+        let test_repo = Box::new(testutils::TestRepository::new());
+        let mut chain = BlockChain::load(test_repo);
+        let wallet = testutils::test_wallet();
+        let address = wallet.address.as_str();
+        chain.mine_block(address); // Earn 50 by mining block
+        let mut txn = chain
+            .build_transaction(
+                address,
+                TransactionBuilder::new().output(&testutils::address("to"), 50),
+            )
+            .unwrap();
+        txn.txn_outs[0].amount = 60;
+        txn.sign(&wallet);
+
+        // When
+        let err = chain.submit_transaction(txn).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, String::from("Invalid transaction"));
         assert_eq!(chain.mempool().len(), 0);
     }
 
+    #[test]
+    fn adding_block_extends_chain() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("some"));
+        let block2 = Block::mine(
+            &testutils::address("some"),
+            &block1.hash,
+            2,
+            1,
+            block1.timestamp + 1,
+            &mut vec![],
+        );
+
+        // When
+        chain.add_block(block2.clone(), "peer").unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.newest_hash, block2.hash);
+        assert_eq!(chain.snapshot.height, 2);
+    }
+
+    #[test]
+    fn cannot_add_block_not_extending_chain() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("some"));
+        let block = Block::mine(
+            &testutils::address("some"),
+            &block1.hash,
+            3,
+            1,
+            Utc::now().timestamp(),
+            &mut vec![],
+        );
+
+        // When
+        let err = chain.add_block(block, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Block does not extend the chain");
+        assert_eq!(chain.snapshot.height, 1);
+    }
+
+    #[test]
+    fn orphan_easier_than_the_chain_allows_is_rejected() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let easy = Block::mine(
+            &testutils::address("miner"),
+            "unknown",
+            2,
+            0,
+            0,
+            &mut vec![],
+        );
+        let far = Block::mine(
+            &testutils::address("miner"),
+            "unknown",
+            7,
+            0,
+            0,
+            &mut vec![],
+        );
+
+        // When
+        let err = chain.add_block(easy, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Orphan difficulty is too low");
+        chain.add_block(far.clone(), "peer").unwrap();
+        assert!(chain.has_block(&far.hash));
+    }
+
+    #[test]
+    fn orphans_connect_once_their_parent_arrives() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut other_chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = other_chain.mine_block(&testutils::address("miner"));
+        let block2 = other_chain.mine_block(&testutils::address("miner"));
+        let block3 = other_chain.mine_block(&testutils::address("miner"));
+
+        // When
+        chain.add_block(block3.clone(), "peer").unwrap();
+        chain.add_block(block2.clone(), "peer").unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.height, 0);
+        assert_eq!(
+            chain.missing_ancestor(&block3.hash),
+            Some(block1.hash.clone())
+        );
+        assert_eq!(chain.missing_ancestor(&block1.hash), None);
+
+        // When
+        chain.add_block(block1, "peer").unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.newest_hash, block3.hash);
+        assert_eq!(chain.missing_ancestor(&block3.hash), None);
+    }
+
+    #[test]
+    fn cannot_add_block_with_invalid_proof_of_work() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("some"));
+        let mut block2 = Block::mine(
+            &testutils::address("some"),
+            &block1.hash,
+            2,
+            1,
+            block1.timestamp + 1,
+            &mut vec![],
+        );
+        block2.nonce += 1;
+
+        // When
+        let err = chain.add_block(block2, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Invalid proof of work");
+    }
+
+    #[test]
+    fn cannot_add_oversized_block() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("some"));
+        let mut block2 = Block::mine(
+            &testutils::address("some"),
+            &block1.hash,
+            2,
+            1,
+            block1.timestamp + 1,
+            &mut vec![],
+        );
+        let payload = Script::new(vec![Op::Push(vec![0; MAX_BLOCK_SIZE])]);
+        block2.transactions.push(Transaction::new(
+            vec![TxnIn::new(&block1.transactions[0].hash, 0, 50)],
+            vec![TxnOut::with_script(payload, 50)],
+        ));
+
+        // When
+        let err = chain.add_block(block2, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Block is too large");
+    }
+
+    #[test]
+    fn cannot_add_block_with_timestamp_before_median_time_past() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("some"));
+        let mine_at = |timestamp| {
+            Block::mine(
+                &testutils::address("some"),
+                &block1.hash,
+                2,
+                1,
+                timestamp,
+                &mut vec![],
+            )
+        };
+
+        // When
+        let err = chain
+            .add_block(mine_at(block1.timestamp), "peer")
+            .unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Block timestamp is not after the median time past");
+
+        // When
+        let mut block2 = mine_at(block1.timestamp + 1);
+        block2.timestamp += 1;
+        let err = chain.add_block(block2, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Invalid proof of work");
+    }
+
+    #[test]
+    fn cannot_add_block_with_timestamp_too_far_in_future() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("some"));
+        let timestamp = chain.adjusted_time() + MAX_FUTURE_BLOCK_TIME + 60;
+        let block2 = Block::mine(
+            &testutils::address("some"),
+            &block1.hash,
+            2,
+            1,
+            timestamp,
+            &mut vec![],
+        );
+
+        // When
+        let err = chain.add_block(block2, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Block timestamp is too far in the future");
+    }
+
+    #[test]
+    fn cannot_add_block_not_matching_checkpoint() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut other = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = other.mine_block(&testutils::address("some"));
+        let mut checkpoints = BTreeMap::new();
+        checkpoints.insert(1, String::from("checkpoint"));
+        chain.set_params(ChainParams {
+            checkpoints,
+            assume_valid: false,
+        });
+
+        // When
+        let err = chain.add_block(block1, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Block does not match checkpoint");
+        assert_eq!(chain.snapshot.height, 0);
+    }
+
+    #[test]
+    fn blocks_up_to_last_checkpoint_can_be_assumed_valid() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut other = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let other1 = other.mine_block(&testutils::address("other"));
+        let invalid = Transaction::new(
+            vec![TxnIn::new("missing", 0, 10)],
+            vec![TxnOut::new(&testutils::address("other"), 10)],
+        );
+        let mine_on = |prev: &Block, txns: Vec<Transaction>| {
+            let address = testutils::address("other");
+            let timestamp = prev.timestamp + 1;
+            Block::mine(&address, &prev.hash, prev.height + 1, 1, timestamp, &mut {
+                txns
+            })
+        };
+        let other2 = mine_on(&other1, vec![invalid]);
+        let other3 = mine_on(&other2, vec![]);
+        chain.add_block(other1, "peer").unwrap();
+
+        let mut checkpoints = BTreeMap::new();
+        checkpoints.insert(3, other3.hash.clone());
+        chain.set_params(ChainParams {
+            checkpoints,
+            assume_valid: true,
+        });
+
+        // When
+        let err = chain.add_block(other2.clone(), "peer").unwrap_err();
+
+        // Then
+        // Without the headers it's unknown whether the block leads to the checkpoint
+        assert_eq!(err.msg, "Invalid transaction");
+        assert_eq!(chain.snapshot.height, 1);
+
+        // When
+        chain
+            .add_headers(&[other2.header(), other3.header()])
+            .unwrap();
+        chain.add_block(other2, "peer").unwrap();
+        chain.add_block(other3.clone(), "peer").unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.newest_hash, other3.hash);
+    }
+
+    #[test]
+    fn blocks_assumed_valid_must_not_pay_more_than_the_reward() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("some"));
+        let mut block2 = Block::mine(
+            &testutils::address("miner"),
+            &block1.hash,
+            2,
+            1,
+            block1.timestamp + 1,
+            &mut vec![],
+        );
+        let coinbase = &mut block2.transactions[0];
+        coinbase.txn_outs[0].amount = 1_000_000;
+        coinbase.hash = coinbase.hash();
+        while !block2.has_valid_proof() {
+            block2.nonce += 1;
+            block2.hash = block2.hash();
+        }
+        let mut checkpoints = BTreeMap::new();
+        checkpoints.insert(2, block2.hash.clone());
+        chain.set_params(ChainParams {
+            checkpoints,
+            assume_valid: true,
+        });
+        chain.add_headers(&[block2.header()]).unwrap();
+
+        // When
+        let err = chain.add_block(block2, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Coinbase pays more than the reward and fees");
+        assert_eq!(chain.snapshot.height, 1);
+    }
+
+    #[test]
+    fn disconnecting_block_restores_spent_outputs() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        let block1 = chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let coinbase = block1.transactions[0].clone();
+        let txn = chain
+            .make_transaction(&wallet.address, &testutils::address("to"), 20, &wallet)
+            .unwrap();
+        let block2 = chain.mine_block(&testutils::address("miner"));
+
+        // When
+        let disconnected = chain.disconnect_tip().unwrap();
+
+        // Then
+        assert_eq!(disconnected, block2);
+        assert_eq!(chain.snapshot.newest_hash, block1.hash);
+        assert_eq!(chain.snapshot.height, 1);
+        assert!(chain.repo.get_utxo(&coinbase.hash, 0).is_some());
+        assert!(chain.repo.get_utxo(&txn.hash, 0).is_none());
+        assert_eq!(chain.balance_by_address(&testutils::address("miner")), 0);
+        assert_eq!(chain.mempool(), vec![txn.clone()]);
+
+        // When
+        chain.add_block(block2.clone(), "peer").unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.newest_hash, block2.hash);
+        assert!(chain.repo.get_utxo(&coinbase.hash, 0).is_none());
+        assert!(chain.repo.get_utxo(&txn.hash, 0).is_some());
+        assert!(chain.mempool().is_empty());
+    }
+
+    #[test]
+    fn invalidating_block_rolls_chain_back_to_its_parent() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let miner = testutils::address("miner");
+        let block1 = chain.mine_block(&miner);
+        let block2 = chain.mine_block(&miner);
+        chain.mine_block(&miner);
+
+        // When
+        chain.invalidate_block(&block2.hash).unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.newest_hash, block1.hash);
+        assert_eq!(chain.balance_by_address(&miner), 50);
+        assert_eq!(chain.repo.load_invalid_blocks(), vec![block2.hash.clone()]);
+        let err = chain.add_block(block2, "peer").unwrap_err();
+        assert_eq!(err.msg, "Block is marked invalid");
+        let err = chain.invalidate_block("unknown").unwrap_err();
+        assert_eq!(err.msg, "Block not found");
+    }
+
+    #[test]
+    fn reconsidering_block_switches_back_to_its_branch() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let miner = testutils::address("miner");
+        chain.mine_block(&miner);
+        let block2 = chain.mine_block(&miner);
+        let block3 = chain.mine_block(&miner);
+        chain.invalidate_block(&block2.hash).unwrap();
+        let fork = chain.mine_block(&testutils::address("other"));
+
+        // When
+        chain.reconsider_block(&block2.hash).unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.newest_hash, block3.hash);
+        assert_eq!(chain.balance_by_address(&miner), 150);
+        assert_eq!(chain.balance_by_address(&testutils::address("other")), 0);
+        assert!(chain.repo.load_invalid_blocks().is_empty());
+        assert!(chain.get_block(fork.hash).is_some());
+    }
+
+    #[test]
+    fn side_branch_is_kept_until_it_has_more_work() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut other_chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("miner"));
+        let block2 = chain.mine_block(&testutils::address("miner"));
+        other_chain.add_block(block1.clone(), "peer").unwrap();
+        let fork2 = other_chain.mine_block(&testutils::address("other"));
+        let fork3 = other_chain.mine_block(&testutils::address("other"));
+
+        // When
+        chain.add_block(fork2.clone(), "peer").unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.newest_hash, block2.hash);
+        let tips = chain.chain_tips();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0].hash, block2.hash);
+        assert_eq!(tips[0].status, TipStatus::Active);
+        assert_eq!(tips[0].branch_length, 0);
+        assert_eq!(tips[0].chain_work, block1.work() + block2.work());
+        assert_eq!(tips[1].hash, fork2.hash);
+        assert_eq!(tips[1].status, TipStatus::ValidFork);
+        assert_eq!(tips[1].branch_length, 1);
+        assert!(!chain.is_active_block(&fork2.hash));
+
+        // When
+        chain.add_block(fork3.clone(), "peer").unwrap();
+
+        // Then
+        assert_eq!(chain.snapshot.newest_hash, fork3.hash);
+        assert!(chain.is_active_block(&fork2.hash));
+        assert!(!chain.is_active_block(&block2.hash));
+        assert_eq!(chain.balance_by_address(&testutils::address("miner")), 50);
+        let tips = chain.chain_tips();
+        assert_eq!(tips[0].hash, fork3.hash);
+        assert_eq!(tips[1].hash, block2.hash);
+        assert_eq!(tips[1].status, TipStatus::ValidFork);
+        let headers = chain.headers_after(std::slice::from_ref(&block1.hash), MAX_HEADERS);
+        assert_eq!(headers, vec![fork2.header(), fork3.header()]);
+
+        // When
+        chain.invalidate_block(&block2.hash).unwrap();
+
+        // Then
+        assert_eq!(chain.chain_tips()[1].status, TipStatus::Invalid);
+    }
+
+    #[test]
+    fn invalid_block_triggering_reorg_is_rejected() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut other_chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let block1 = chain.mine_block(&testutils::address("miner"));
+        chain.mine_block(&testutils::address("miner"));
+        other_chain.add_block(block1.clone(), "peer").unwrap();
+        let fork2 = other_chain.mine_block(&testutils::address("other"));
+        let mut fork3 = Block::mine(
+            &testutils::address("other"),
+            &fork2.hash,
+            3,
+            1,
+            fork2.timestamp + 1,
+            &mut vec![],
+        );
+        let coinbase = &mut fork3.transactions[0];
+        coinbase.txn_outs[0].amount = 1_000_000;
+        coinbase.hash = coinbase.hash();
+        while !fork3.has_valid_proof() {
+            fork3.nonce += 1;
+            fork3.hash = fork3.hash();
+        }
+        chain.add_block(fork2.clone(), "peer").unwrap();
+
+        // When
+        let err = chain.add_block(fork3.clone(), "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Coinbase pays more than the reward and fees");
+        // The valid part of the branch has as much work as the old tip
+        assert_eq!(chain.snapshot.newest_hash, fork2.hash);
+        assert_eq!(chain.balance_by_address(&testutils::address("miner")), 50);
+        assert!(chain.invalid_blocks.contains(&fork3.hash));
+    }
+
+    #[test]
+    fn blocks_are_downloaded_after_their_headers() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut other_chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        for _ in 0..3 {
+            other_chain.mine_block(&testutils::address("miner"));
+        }
+        let headers = other_chain.headers_after(&[], MAX_HEADERS);
+
+        // When
+        chain.add_headers(&headers).unwrap();
+
+        // Then
+        let status = chain.sync_status();
+        assert_eq!(status.header_height, 3);
+        assert_eq!(status.block_height, 0);
+        assert!(!status.is_synced);
+        assert_eq!(chain.chain_tips()[0].status, TipStatus::HeadersOnly);
+        let hashes = chain.blocks_to_download("peer", 0);
+        assert_eq!(
+            hashes,
+            headers
+                .iter()
+                .map(|header| header.hash.clone())
+                .collect::<Vec<String>>()
+        );
+        assert!(chain.blocks_to_download("other-peer", 0).is_empty());
+
+        // When
+        for hash in hashes.into_iter().rev() {
+            chain
+                .add_block(other_chain.get_block(hash).unwrap(), "peer")
+                .unwrap();
+        }
+
+        // Then
+        let status = chain.sync_status();
+        assert_eq!(status.block_height, 3);
+        assert_eq!(status.blocks_in_flight, 0);
+        assert!(status.is_synced);
+        assert_eq!(chain.snapshot.newest_hash, headers[2].hash);
+    }
+
+    #[test]
+    fn cannot_add_headers_failing_validation() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut other_chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        other_chain.mine_block(&testutils::address("miner"));
+        other_chain.mine_block(&testutils::address("miner"));
+        let headers = other_chain.headers_after(&[], MAX_HEADERS);
+        let mut tampered = headers[0].clone();
+        tampered.txns_hash = String::from("other");
+
+        // When
+        let unlinked = chain.add_headers(&headers[1..]).unwrap_err();
+        let invalid_proof = chain.add_headers(&[tampered]).unwrap_err();
+
+        // Then
+        assert_eq!(unlinked.msg, "Header does not extend a known header");
+        assert_eq!(invalid_proof.msg, "Invalid proof of work");
+        assert_eq!(chain.sync_status().header_height, 0);
+    }
+
+    #[test]
+    fn block_locator_is_exponentially_spaced() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut blocks = vec![];
+        for _ in 0..15 {
+            blocks.push(chain.mine_block(&testutils::address("miner")));
+        }
+
+        // When
+        let locator = chain.block_locator();
+
+        // Then
+        let heights: Vec<u64> = locator
+            .iter()
+            .map(|hash| chain.get_block(hash.clone()).unwrap().height)
+            .collect();
+        assert_eq!(heights, vec![15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 4, 1]);
+        assert_eq!(locator[0], blocks[14].hash);
+    }
+
+    #[test]
+    fn headers_start_after_fork_point_of_locator() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let mut other_chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        chain.mine_block(&testutils::address("miner"));
+        let block2 = chain.mine_block(&testutils::address("miner"));
+        let block3 = chain.mine_block(&testutils::address("miner"));
+        for block in chain.all_blocks().into_iter().rev().take(2) {
+            other_chain.add_block(block, "peer").unwrap();
+        }
+        let behind_locator = other_chain.block_locator();
+        other_chain.mine_block(&testutils::address("other"));
+        other_chain.mine_block(&testutils::address("other"));
+
+        // When
+        let behind = chain.headers_after(&behind_locator, MAX_HEADERS);
+        let forked = chain.headers_after(&other_chain.block_locator(), MAX_HEADERS);
+        let unknown = chain.headers_after(&[String::from("unknown")], 2);
+
+        // Then
+        assert_eq!(behind, vec![block3.header()]);
+        assert_eq!(forked, vec![block3.header()]);
+        assert_eq!(unknown.len(), 2);
+        assert_eq!(unknown[1], block2.header());
+    }
+
+    #[test]
+    fn cannot_spend_output_spent_in_chain() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = build_payment(&chain, &wallet, 0, false);
+        let double_spend = build_payment(&chain, &wallet, 10, false);
+        chain.submit_transaction(txn).unwrap();
+        chain.mine_block(&testutils::address("miner"));
+
+        // When
+        let err = chain.submit_transaction(double_spend).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Invalid transaction");
+    }
+
+    #[test]
+    fn median_time_past_is_median_of_recent_blocks() {
+        // Given
+        let test_repo = testutils::TestRepository::new();
+        let mut snapshot = BlockChainSnapshot::new();
+        let mut prev_hash = String::from("");
+        for (height, timestamp) in [(1, 100), (2, 400), (3, 200)] {
+            let block = Block::mine(
+                &testutils::address("some"),
+                &prev_hash,
+                height,
+                1,
+                timestamp,
+                &mut vec![],
+            );
+            test_repo.save_block(&block).unwrap();
+            prev_hash = block.hash;
+        }
+        snapshot.newest_hash = prev_hash;
+        snapshot.height = 3;
+        test_repo.save_snapshot(&snapshot).unwrap();
+
+        // When
+        let chain = BlockChain::load(Box::new(test_repo));
+
+        // Then
+        assert_eq!(chain.median_time_past(), 200);
+    }
+
+    #[test]
+    fn height_locked_transaction_enters_mempool_after_lock_time() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        let address = wallet.address.as_str();
+        chain.mine_block(address); // Earn 50 by mining block
+        let builder = TransactionBuilder::new()
+            .output(&testutils::address("to"), 20)
+            .lock_time(2);
+        let mut txn = chain.build_transaction(address, builder).unwrap();
+        txn.sign(&wallet);
+
+        // When
+        let err = chain.submit_transaction(txn.clone()).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Transaction is time-locked");
+
+        // When
+        chain.mine_block(&testutils::address("miner"));
+        chain.submit_transaction(txn).unwrap();
+
+        // Then
+        assert_eq!(chain.mempool().len(), 1);
+    }
+
+    #[test]
+    fn cannot_add_block_with_time_locked_transaction() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        let address = wallet.address.as_str();
+        let block1 = chain.mine_block(address); // Earn 50 by mining block
+        let builder = TransactionBuilder::new()
+            .output(&testutils::address("to"), 20)
+            .lock_time(5);
+        let mut txn = chain.build_transaction(address, builder).unwrap();
+        txn.sign(&wallet);
+        let block2 = Block::mine(
+            &testutils::address("miner"),
+            &block1.hash,
+            2,
+            1,
+            block1.timestamp + 1,
+            &mut vec![txn],
+        );
+
+        // When
+        let err = chain.add_block(block2, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Transaction is time-locked");
+    }
+
+    #[test]
+    fn relatively_locked_output_is_spendable_after_confirmations() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        let address = wallet.address.as_str();
+        chain.mine_block(address); // Earn 50 by mining block
+        let builder = TransactionBuilder::new().locked_output(address, 50, RelativeLock::Blocks(2));
+        let mut txn = chain.build_transaction(address, builder).unwrap();
+        txn.sign(&wallet);
+        chain.submit_transaction(txn).unwrap();
+        chain.mine_block(&testutils::address("miner")); // Locked output confirmed at height 2
+
+        // When
+        let err = chain
+            .make_transaction(address, &testutils::address("to"), 50, &wallet)
+            .unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Output is time-locked");
+
+        // When
+        chain.mine_block(&testutils::address("miner"));
+        chain
+            .make_transaction(address, &testutils::address("to"), 50, &wallet)
+            .unwrap();
+
+        // Then
+        assert_eq!(chain.mempool().len(), 1);
+    }
+
+    #[test]
+    fn spending_multisig_output_requires_enough_signatures() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallets = [
+            testutils::test_wallet(),
+            testutils::test_wallet(),
+            testutils::test_wallet(),
+        ];
+        let public_keys = wallets
+            .iter()
+            .map(|wallet| hex::encode(wallet.public_key()))
+            .collect();
+        let multisig = MultiSig::new(2, public_keys).unwrap();
+        chain.mine_block(&multisig.address(Network::Mainnet).unwrap()); // Earn 50 by mining block
+        let builder = TransactionBuilder::new()
+            .output(&testutils::address("to"), 20)
+            .multisig(multisig.clone());
+        let mut txn = chain
+            .build_transaction(&multisig.address(Network::Mainnet).unwrap(), builder)
+            .unwrap();
+
+        // When
+        txn.sign(&wallets[0]);
+        txn.sign(&wallets[0]);
+        let err = chain.submit_transaction(txn.clone()).unwrap_err();
+
+        // Then
+        assert_eq!(txn.txn_ins[0].script_sig.pushed_data().len(), 2); // A signature and the redeem script
+        assert_eq!(err.msg, "Invalid transaction");
+
+        // When
+        txn.sign(&wallets[2]);
+        chain.submit_transaction(txn).unwrap();
+        chain.mine_block(&testutils::address("miner"));
+
+        // Then
+        assert_eq!(
+            chain.balance_by_address(&multisig.address(Network::Mainnet).unwrap()),
+            30
+        );
+        assert_eq!(chain.balance_by_address(&testutils::address("to")), 20);
+    }
+
+    #[test]
+    fn cannot_spend_multisig_output_with_signatures_of_other_keys() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let (wallet1, wallet2) = (testutils::test_wallet(), testutils::test_wallet());
+        let outsider = testutils::test_wallet();
+        let public_keys = vec![
+            hex::encode(wallet1.public_key()),
+            hex::encode(wallet2.public_key()),
+        ];
+        let multisig = MultiSig::new(2, public_keys).unwrap();
+        chain.mine_block(&multisig.address(Network::Mainnet).unwrap()); // Earn 50 by mining block
+        let builder = TransactionBuilder::new()
+            .output(&testutils::address("to"), 50)
+            .multisig(multisig.clone());
+        let mut txn = chain
+            .build_transaction(&multisig.address(Network::Mainnet).unwrap(), builder)
+            .unwrap();
+        txn.sign(&wallet1);
+        let msg = hex::encode(&txn.hash);
+        let signature = hex::decode(outsider.sign(&msg)).unwrap();
+        txn.txn_ins[0].script_sig.0.insert(0, Op::Push(signature));
+
+        // When
+        let err = chain.submit_transaction(txn).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Invalid transaction");
+    }
+
+    fn lock_in_htlc(
+        chain: &mut BlockChain,
+        wallet: &Wallet,
+        recipient: &Wallet,
+        timeout: u64,
+    ) -> (String, i64) {
+        let htlc = Htlc {
+            hash: hex::encode(Sha256::digest(b"secret")),
+            recipient: recipient.address.clone(),
+            refund: wallet.address.clone(),
+            timeout,
+        };
+        let builder = TransactionBuilder::new().script_output(htlc.script().unwrap(), 50);
+        let mut txn = chain.build_transaction(&wallet.address, builder).unwrap();
+        txn.sign(wallet);
+        chain.submit_transaction(txn.clone()).unwrap();
+        chain.mine_block(&testutils::address("miner"));
+        (txn.hash, 0)
+    }
+
+    #[test]
+    fn htlc_is_claimed_with_preimage_which_is_revealed_on_chain() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let (wallet, recipient) = (testutils::test_wallet(), testutils::test_wallet());
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let (txn_hash, idx) = lock_in_htlc(&mut chain, &wallet, &recipient, 10);
+
+        // When
+        let claim_txn = chain
+            .claim_htlc(&txn_hash, idx, b"secret", &recipient)
+            .unwrap();
+        chain.mine_block(&testutils::address("miner"));
+
+        // Then
+        assert_eq!(chain.balance_by_address(&recipient.address), 50);
+        assert_eq!(
+            chain.htlc_preimage(&claim_txn.hash),
+            Some(b"secret".to_vec())
+        );
+    }
+
+    #[test]
+    fn cannot_claim_htlc_with_wrong_preimage_or_key() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let (wallet, recipient) = (testutils::test_wallet(), testutils::test_wallet());
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let (txn_hash, idx) = lock_in_htlc(&mut chain, &wallet, &recipient, 10);
+
+        // Then
+        let err = chain
+            .claim_htlc(&txn_hash, idx, b"guess", &recipient)
+            .unwrap_err();
+        assert_eq!(err.msg, "Invalid transaction");
+        let err = chain
+            .claim_htlc(&txn_hash, idx, b"secret", &wallet)
+            .unwrap_err();
+        assert_eq!(err.msg, "Invalid transaction");
+    }
+
+    #[test]
+    fn htlc_is_refunded_only_after_timeout() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let (wallet, recipient) = (testutils::test_wallet(), testutils::test_wallet());
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let (txn_hash, idx) = lock_in_htlc(&mut chain, &wallet, &recipient, 3);
+
+        // When
+        let err = chain.refund_htlc(&txn_hash, idx, &wallet).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Transaction is time-locked");
+
+        // When
+        chain.mine_block(&testutils::address("miner")); // Next block is past the timeout
+        let err = chain
+            .claim_htlc(&txn_hash, idx, b"secret", &recipient)
+            .unwrap_err();
+        chain.refund_htlc(&txn_hash, idx, &wallet).unwrap();
+        chain.mine_block(&testutils::address("miner"));
+
+        // Then
+        assert_eq!(err.msg, "HTLC has expired");
+        assert_eq!(chain.balance_by_address(&wallet.address), 50);
+        assert_eq!(chain.balance_by_address(&recipient.address), 0);
+    }
+
+    #[test]
+    fn htlc_claim_with_any_true_selector_expires() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let (wallet, recipient) = (testutils::test_wallet(), testutils::test_wallet());
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let (txn_hash, idx) = lock_in_htlc(&mut chain, &wallet, &recipient, 3);
+        chain.mine_block(&testutils::address("miner")); // Next block is past the timeout
+        let (mut txn, _) = chain.htlc_spend(&txn_hash, idx, &recipient).unwrap();
+        txn.txn_ins[0].script_sig = Script::new(vec![
+            Op::Push(txn.signature(&recipient)),
+            Op::Push(recipient.public_key()),
+            Op::Push(b"secret".to_vec()),
+            Op::Push(vec![2]),
+        ]);
+
+        // When
+        let err = chain.submit_transaction(txn).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "HTLC has expired");
+    }
+
+    #[test]
+    fn data_output_is_anchored_in_block_without_affecting_balance() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let builder = TransactionBuilder::new().data_output(b"document-hash");
+        let mut txn = chain.build_transaction(&wallet.address, builder).unwrap();
+        txn.sign(&wallet);
+        chain.submit_transaction(txn).unwrap();
+
+        // When
+        let block = chain.mine_block(&testutils::address("miner"));
+
+        // Then
+        assert_eq!(chain.balance_by_address(&wallet.address), 50);
+        assert_eq!(chain.find_data_block(b"document-hash"), Some(block));
+        assert_eq!(chain.find_data_block(b"other-hash"), None);
+
+        // When
+        chain.disconnect_tip().unwrap();
+
+        // Then
+        assert_eq!(chain.find_data_block(b"document-hash"), None);
+    }
+
+    #[test]
+    fn cannot_submit_transaction_with_invalid_data_output() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        let block = chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let coinbase = &block.transactions[0];
+        let txn_in = TxnIn::new(&coinbase.hash, 0, 50);
+
+        for txn_out in [
+            TxnOut::with_script(Script::data(b"document-hash"), 10),
+            TxnOut::data(&[0; MAX_DATA_SIZE + 1]),
+        ] {
+            // When
+            let mut txn = Transaction::new(
+                vec![txn_in.clone()],
+                vec![TxnOut::new(&wallet.address, 40), txn_out],
+            );
+            txn.sign(&wallet);
+
+            // Then
+            let err = chain.submit_transaction(txn).unwrap_err();
+            assert_eq!(err.msg, "Invalid transaction");
+        }
+    }
+
+    fn build_payment(
+        chain: &BlockChain,
+        wallet: &Wallet,
+        fee_rate: u64,
+        replaceable: bool,
+    ) -> Transaction {
+        let builder = TransactionBuilder::new()
+            .output(&testutils::address("to"), 20)
+            .fee_rate(fee_rate)
+            .replaceable(replaceable);
+        let mut txn = chain.build_transaction(&wallet.address, builder).unwrap();
+        txn.sign(wallet);
+        txn
+    }
+
+    #[test]
+    fn miner_collects_fees_of_block_transactions() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = build_payment(&chain, &wallet, 10, false);
+        chain.submit_transaction(txn.clone()).unwrap();
+
+        // When
+        chain.mine_block(&testutils::address("miner"));
+
+        // Then
+        assert!(txn.fee() > 0);
+        assert_eq!(
+            chain.balance_by_address(&testutils::address("miner")),
+            MINER_REWARD + txn.fee()
+        );
+    }
+
+    #[test]
+    fn cannot_add_block_paying_more_than_reward_and_fees() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        let block1 = chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = build_payment(&chain, &wallet, 10, false);
+        let mut block2 = Block::mine(
+            &testutils::address("miner"),
+            &block1.hash,
+            2,
+            1,
+            block1.timestamp + 1,
+            &mut vec![txn],
+        );
+        // Keep the coinbase claiming the fee of the dropped transaction
+        block2.transactions.truncate(1);
+        while !block2.has_valid_proof() {
+            block2.nonce += 1;
+            block2.hash = block2.hash();
+        }
+
+        // When
+        let err = chain.add_block(block2, "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Coinbase pays more than the reward and fees");
+    }
+
+    #[test]
+    fn cannot_add_block_with_coinbase_reusing_unspent_txn_hash() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let victim = testutils::address("victim");
+        let block1 = chain.mine_block(&victim);
+        let mut block2 = Block::mine(
+            &testutils::address("miner"),
+            &block1.hash,
+            2,
+            1,
+            block1.timestamp + 1,
+            &mut vec![],
+        );
+        block2.transactions[0].hash = block1.transactions[0].hash.clone();
+        while !block2.has_valid_proof() {
+            block2.nonce += 1;
+            block2.hash = block2.hash();
+        }
+
+        // When
+        let err = chain.add_block(block2.clone(), "peer").unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Invalid coinbase transaction");
+        let err = chain.connect_block(&block2).unwrap_err();
+        assert_eq!(err.msg, "Transaction outputs already exist");
+        assert_eq!(chain.balance_by_address(&victim), 50);
+        assert_eq!(chain.balance_by_address(&testutils::address("miner")), 0);
+    }
+
+    #[test]
+    fn cannot_submit_transaction_conflicting_with_non_replaceable_one() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = build_payment(&chain, &wallet, 10, false);
+        let conflict = build_payment(&chain, &wallet, 50, true);
+        chain.submit_transaction(txn.clone()).unwrap();
+
+        // When
+        let err = chain.submit_transaction(conflict).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Transaction conflicts with the mempool");
+        assert_eq!(chain.mempool(), vec![txn]);
+    }
+
+    #[test]
+    fn replaceable_transaction_is_replaced_by_one_paying_higher_fee() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = build_payment(&chain, &wallet, 10, true);
+        let cheaper = build_payment(&chain, &wallet, 5, true);
+        let replacement = build_payment(&chain, &wallet, 50, false);
+        chain.submit_transaction(txn.clone()).unwrap();
+
+        // When
+        let err = chain.submit_transaction(cheaper).unwrap_err();
+        chain.submit_transaction(replacement.clone()).unwrap();
+
+        // Then
+        assert_eq!(err.msg, "Replacement must pay a higher fee");
+        assert_eq!(chain.mempool(), vec![replacement]);
+    }
+
+    #[test]
+    fn replaced_transaction_is_kept_when_replacement_does_not_fit_in_mempool() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = build_payment(&chain, &wallet, 10, true);
+        let builder = TransactionBuilder::new()
+            .output(&testutils::address("to"), 10)
+            .output(&testutils::address("other"), 10)
+            .fee_rate(50);
+        let mut replacement = chain.build_transaction(&wallet.address, builder).unwrap();
+        replacement.sign(&wallet);
+        chain.submit_transaction(txn.clone()).unwrap();
+        chain.set_mempool_config(MempoolConfig {
+            max_size: txn.size(),
+            ..Default::default()
+        });
+
+        // When
+        let err = chain.submit_transaction(replacement).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Mempool is full");
+        assert_eq!(chain.mempool(), vec![txn]);
+    }
+
+    #[test]
+    fn bump_fee_replaces_transaction_paying_from_change() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = build_payment(&chain, &wallet, 10, true);
+        chain.submit_transaction(txn.clone()).unwrap();
+
+        // When
+        let bumped = chain.bump_fee(&txn.hash, 50, &wallet).unwrap();
+
+        // Then
+        assert_eq!(chain.mempool(), vec![bumped.clone()]);
+        assert!(bumped.fee() > txn.fee());
+        assert!(bumped.fee_rate() >= 50);
+        assert_eq!(bumped.txn_outs[1], txn.txn_outs[1]);
+        assert_eq!(bumped.txn_outs[0].amount + bumped.fee(), 30);
+        let err = chain.bump_fee(&bumped.hash, 10, &wallet).unwrap_err();
+        assert_eq!(err.msg, "Fee rate must be higher");
+    }
+
+    #[test]
+    fn unconfirmed_change_can_be_spent() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        let address = wallet.address.as_str();
+        chain.mine_block(address); // Earn 50 by mining block
+        chain
+            .make_transaction(address, &testutils::address("to"), 20, &wallet)
+            .unwrap();
+
+        // When
+        let child = chain
+            .make_transaction(address, &testutils::address("to"), 25, &wallet)
+            .unwrap();
+        chain.mine_block(&testutils::address("miner"));
+
+        // Then
+        assert_eq!(child.txn_ins[0].amount, 30);
+        assert_eq!(chain.balance_by_address(address), 5);
+        assert_eq!(chain.balance_by_address(&testutils::address("to")), 45);
+    }
+
+    #[test]
+    fn cannot_chain_too_many_unconfirmed_transactions() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        let address = wallet.address.as_str();
+        chain.mine_block(address); // Earn 50 by mining block
+        for _ in 0..MAX_UNCONFIRMED_ANCESTORS {
+            chain
+                .make_transaction(address, &testutils::address("to"), 1, &wallet)
+                .unwrap();
+        }
+
+        // When
+        let err = chain
+            .make_transaction(address, &testutils::address("to"), 1, &wallet)
+            .unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Too many unconfirmed ancestors");
+    }
+
+    #[test]
+    fn block_template_lets_child_pay_for_parent() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let parent = build_payment(&chain, &wallet, 0, false);
+        chain.submit_transaction(parent.clone()).unwrap();
+        let unrelated = build_payment(&chain, &wallet, 10, false);
+        chain.submit_transaction(unrelated.clone()).unwrap();
+        let mut child = TransactionBuilder::new()
+            .inputs(vec![UTxnOut::new(&parent.hash, 0, 30)])
+            .output(&testutils::address("to"), 10)
+            .change_address(&wallet.address)
+            .fee_rate(40)
+            .build()
+            .unwrap();
+        child.sign(&wallet);
+        chain.submit_transaction(child.clone()).unwrap();
+
+        // When
+        let template = chain.block_template();
+
+        // Then
+        assert_eq!(template, vec![parent, child, unrelated]);
+    }
+
+    #[test]
+    fn cannot_submit_transaction_below_mempool_policy() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = build_payment(&chain, &wallet, 10, false);
+
+        // When
+        chain.set_policy(Policy {
+            min_relay_fee_rate: 20,
+            ..Default::default()
+        });
+        let err = chain.submit_transaction(txn.clone()).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Fee rate is below the minimum relay fee");
+
+        // When
+        chain.set_policy(Policy::default());
+        chain.set_mempool_config(MempoolConfig {
+            max_size: txn.size() - 1,
+            ..Default::default()
+        });
+        let err = chain.submit_transaction(txn).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Mempool is full");
+        assert!(chain.mempool().is_empty());
+    }
+
+    #[test]
+    fn dust_is_rejected_by_policy_but_valid_by_consensus() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let builder = TransactionBuilder::new().output(&testutils::address("to"), 0);
+        let mut txn = chain.build_transaction(&wallet.address, builder).unwrap();
+        txn.sign(&wallet);
+
+        // When
+        let err = chain.submit_transaction(txn.clone()).unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Output is dust");
+        assert!(chain.validate_transaction(&txn, &[]));
+
+        // When
+        chain.set_policy(Policy {
+            dust_threshold: 0,
+            ..Default::default()
+        });
+
+        // Then
+        assert!(chain.submit_transaction(txn).is_ok());
+    }
+
+    #[test]
+    fn wallet_transactions_pay_estimated_fee_by_default() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        for _ in 0..4 {
+            chain.mine_block(&wallet.address); // Earn 50 by mining block
+        }
+        assert_eq!(chain.estimate_fee(1), 0);
+        for _ in 0..3 {
+            let txn = build_payment(&chain, &wallet, 40, false);
+            chain.submit_transaction(txn).unwrap();
+        }
+        chain.mine_block(&wallet.address); // Confirms the payments within a block
+
+        // When
+        let txn = chain
+            .make_transaction(&wallet.address, &testutils::address("to"), 1, &wallet)
+            .unwrap();
+
+        // Then
+        assert_eq!(chain.estimate_fee(1), 32);
+        assert_eq!(chain.estimate_fee(DEFAULT_TARGET_BLOCKS), 32);
+        assert!(txn.fee_rate() >= 32);
+    }
+
+    #[test]
+    fn mempool_is_reloaded_and_revalidated_on_restart() {
+        // Given
+        let mut chain = BlockChain::load(Box::new(testutils::TestRepository::new()));
+        let wallet = testutils::test_wallet();
+        chain.mine_block(&wallet.address); // Earn 50 by mining block
+        let txn = chain
+            .make_transaction(&wallet.address, &testutils::address("to"), 20, &wallet)
+            .unwrap();
+        let invalid = Transaction::new(
+            vec![TxnIn::new("missing", 0, 10)],
+            vec![TxnOut::new(&testutils::address("to"), 10)],
+        );
+        let repo = testutils::TestRepository::new();
+        for block in chain.all_blocks().iter() {
+            repo.save_block(block).unwrap();
+        }
+        repo.save_snapshot(&chain.snapshot).unwrap();
+        let mut entries = chain.mempool.entries();
+        entries.push(MempoolEntry {
+            txn: invalid,
+            time: Utc::now().timestamp(),
+            height: chain.snapshot.height,
+        });
+        repo.save_mempool(&entries).unwrap();
+
+        // When
+        let chain = BlockChain::load(Box::new(repo));
+
+        // Then
+        assert_eq!(chain.mempool(), vec![txn]);
+        assert_eq!(chain.repo.load_mempool(), chain.mempool.entries());
+    }
+
     #[test]
     fn cannot_make_transaction_when_verification_failed() {
         // Given
         let test_repo = Box::new(testutils::TestRepository::new());
         let mut chain = BlockChain::load(test_repo);
         let wallet = testutils::test_wallet();
-        let wrong_address = "04C72F87E9176F814714F5EF9DE2414863937D1391B02EF8BA576C89A2F69130E6032A56D01750F2638146BC898FA59695813462A49BA24B85003304DFF2BF76D4";
-        chain.mine_block(wrong_address); // Earn 50 by mining block
+        let wrong_address = testutils::address("wrong");
+        chain.mine_block(&wrong_address); // Earn 50 by mining block
 
         // When
         let err = chain
-            .make_transaction(wrong_address, "to-address", 20, &wallet)
+            .make_transaction(&wrong_address, &testutils::address("to"), 20, &wallet)
             .unwrap_err();
 
         // Then
@@ -432,7 +2967,7 @@ mod tests {
 
         // When
         let err = chain
-            .make_transaction(address, "to-address", 60, &wallet)
+            .make_transaction(address, &testutils::address("to"), 60, &wallet)
             .unwrap_err();
 
         // Then
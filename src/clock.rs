@@ -0,0 +1,152 @@
+use chrono::Utc;
+
+// Bound of peers whose clock offsets are kept
+const MAX_SAMPLES: usize = 200;
+// Peers needed before their clocks are trusted over the local one
+const MIN_SAMPLES: usize = 5;
+// Largest offset from the local clock which is applied
+pub const MAX_OFFSET: i64 = 70 * 60;
+
+// Local clock corrected by the median offset of the peer clocks
+#[derive(Debug, Default)]
+pub struct AdjustedClock {
+    // Seconds each peer host is ahead of the local clock, oldest first
+    samples: Vec<(String, i64)>,
+    offset: i64,
+    out_of_sync: bool,
+}
+
+impl AdjustedClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now(&self) -> i64 {
+        Utc::now().timestamp() + self.offset
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    // Whether the peers disagree with the local clock by more than can be corrected
+    pub fn is_out_of_sync(&self) -> bool {
+        self.out_of_sync
+    }
+
+    // Records the offset of the peer at `address`, keeping one sample per host so
+    // a peer can't outweigh the others by connecting from many ports
+    pub fn add_sample(&mut self, address: &str, offset: i64) {
+        let host = host(address);
+        self.samples.retain(|(sampled, _)| sampled != host);
+        self.samples.push((host.to_string(), offset));
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.remove(0);
+        }
+        if self.samples.len() < MIN_SAMPLES {
+            return;
+        }
+        // Offsets beyond the bound are left out, so they can't pull the median
+        // towards them, but most peers having them means the local clock is off
+        let mut offsets: Vec<i64> = self
+            .samples
+            .iter()
+            .map(|(_, offset)| *offset)
+            .filter(|offset| (-MAX_OFFSET..=MAX_OFFSET).contains(offset))
+            .collect();
+        self.out_of_sync = offsets.len() * 2 < self.samples.len();
+        // The local clock counts as a sample too
+        offsets.push(0);
+        offsets.sort_unstable();
+        self.offset = if self.out_of_sync {
+            0
+        } else {
+            offsets[offsets.len() / 2]
+        };
+    }
+}
+
+// Host part of a `host:port` address
+fn host(address: &str) -> &str {
+    address
+        .rsplit_once(':')
+        .map_or(address, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_is_median_of_peers_once_there_are_enough() {
+        // Given
+        let mut clock = AdjustedClock::new();
+        for (peer, offset) in [("a", 10), ("b", 20), ("c", 3000), ("d", 30)] {
+            clock.add_sample(peer, offset);
+        }
+        assert_eq!(clock.offset(), 0);
+
+        // When
+        clock.add_sample("e", 40);
+
+        // Then
+        assert_eq!(clock.offset(), 30);
+
+        // When
+        clock.add_sample("a", 50);
+
+        // Then
+        assert_eq!(clock.offset(), 40);
+        assert!(!clock.is_out_of_sync());
+    }
+
+    #[test]
+    fn offset_beyond_bound_is_not_applied() {
+        // Given
+        let mut clock = AdjustedClock::new();
+
+        // When
+        for peer in ["a", "b", "c", "d", "e"] {
+            clock.add_sample(peer, -(MAX_OFFSET + 1));
+        }
+
+        // Then
+        assert_eq!(clock.offset(), 0);
+        assert!(clock.is_out_of_sync());
+    }
+
+    #[test]
+    fn offsets_beyond_bound_do_not_move_the_median() {
+        // Given
+        let mut clock = AdjustedClock::new();
+        for (peer, offset) in [("a", 10), ("b", 20), ("c", 30), ("d", 40)] {
+            clock.add_sample(peer, offset);
+        }
+
+        // When
+        clock.add_sample("e", i64::MAX);
+        clock.add_sample("f", i64::MIN);
+
+        // Then
+        assert_eq!(clock.offset(), 20);
+        assert!(!clock.is_out_of_sync());
+    }
+
+    #[test]
+    fn ports_of_one_host_count_as_one_sample() {
+        // Given
+        let mut clock = AdjustedClock::new();
+        for port in 4000..4004 {
+            clock.add_sample(&format!("10.0.0.1:{}", port), 3000);
+        }
+        for peer in ["10.0.0.2:4000", "10.0.0.3:4000", "[::1]:4000", "::2:4000"] {
+            clock.add_sample(peer, 0);
+        }
+
+        // Then
+        assert_eq!(clock.samples.len(), 5);
+        assert_eq!(clock.offset(), 0);
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+// Fee rates are grouped in buckets doubling in size: 0, 1, 2-3, 4-7, ...
+const BUCKET_COUNT: usize = 24;
+// Blocks confirmations are remembered for
+const HISTORY_BLOCKS: u64 = 1000;
+// Share of transactions which must confirm in time for a bucket to be estimated
+const SUCCESS_PERCENT: u64 = 85;
+// Transactions a range of buckets needs before it's trusted
+const MIN_SAMPLES: u64 = 3;
+pub const DEFAULT_TARGET_BLOCKS: u64 = 6;
+pub const MAX_TARGET_BLOCKS: u64 = 144;
+
+fn bucket(fee_rate: u64) -> usize {
+    (u64::BITS - fee_rate.leading_zeros()).min(BUCKET_COUNT as u32 - 1) as usize
+}
+
+// Lowest fee rate of the bucket
+fn bucket_fee_rate(bucket: usize) -> u64 {
+    match bucket {
+        0 => 0,
+        _ => 1 << (bucket - 1),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+struct Confirmation {
+    bucket: usize,
+    // Blocks mined from when the transaction entered the mempool until it was confirmed
+    blocks: u64,
+    height: u64,
+}
+
+// Learns fee rates from how many blocks pending transactions of each bucket took to confirm
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone, Default)]
+pub struct FeeEstimator {
+    confirmations: Vec<Confirmation>,
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records the fee rates of the mempool transactions a block at `height` confirmed,
+    // along with the heights of the chain when they entered the mempool
+    pub fn record_block(&mut self, height: u64, confirmed: &[(u64, u64)]) {
+        self.confirmations
+            .retain(|confirmation| confirmation.height + HISTORY_BLOCKS > height);
+        for (fee_rate, entry_height) in confirmed.iter() {
+            self.confirmations.push(Confirmation {
+                bucket: bucket(*fee_rate),
+                blocks: height.saturating_sub(*entry_height).max(1),
+                height,
+            });
+        }
+    }
+
+    // Lowest fee rate at which transactions confirmed within `target_blocks`,
+    // counting the `pending` ones which have waited longer, by fee rate and blocks waited, as failures
+    pub fn estimate(&self, target_blocks: u64, pending: &[(u64, u64)]) -> Option<u64> {
+        let mut successes = [0; BUCKET_COUNT];
+        let mut totals = [0; BUCKET_COUNT];
+        for confirmation in self.confirmations.iter() {
+            totals[confirmation.bucket] += 1;
+            if confirmation.blocks <= target_blocks {
+                successes[confirmation.bucket] += 1;
+            }
+        }
+        for (fee_rate, blocks_waited) in pending.iter() {
+            if *blocks_waited >= target_blocks {
+                totals[bucket(*fee_rate)] += 1;
+            }
+        }
+
+        // Walks down from the highest fee rates while enough transactions keep confirming in time
+        let mut estimate = None;
+        let (mut success, mut total) = (0, 0);
+        for bucket in (0..BUCKET_COUNT).rev() {
+            success += successes[bucket];
+            total += totals[bucket];
+            if total < MIN_SAMPLES {
+                continue;
+            }
+            if success * 100 < total * SUCCESS_PERCENT {
+                break;
+            }
+            estimate = Some(bucket_fee_rate(bucket));
+            (success, total) = (0, 0);
+        }
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_rates_are_bucketed_by_powers_of_two() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 1);
+        assert_eq!(bucket(40), 6);
+        assert_eq!(bucket_fee_rate(bucket(40)), 32);
+        assert_eq!(bucket(u64::MAX), BUCKET_COUNT - 1);
+    }
+
+    #[test]
+    fn estimate_is_lowest_bucket_confirming_within_target() {
+        // Given
+        let mut estimator = FeeEstimator::new();
+        estimator.record_block(10, &[(100, 9), (100, 9), (100, 9)]);
+        estimator.record_block(11, &[(40, 10), (40, 10), (40, 10)]);
+        estimator.record_block(13, &[(10, 10), (10, 10), (10, 10)]);
+
+        // Then
+        assert_eq!(estimator.estimate(1, &[]), Some(32));
+        assert_eq!(estimator.estimate(3, &[]), Some(8));
+        assert_eq!(FeeEstimator::new().estimate(1, &[]), None);
+    }
+
+    #[test]
+    fn pending_transactions_count_against_their_bucket() {
+        // Given
+        let mut estimator = FeeEstimator::new();
+        estimator.record_block(10, &[(40, 9), (40, 9), (40, 9), (10, 9), (10, 9), (10, 9)]);
+
+        // When
+        let pending = [(10, 2), (10, 2)];
+
+        // Then
+        assert_eq!(estimator.estimate(1, &[]), Some(8));
+        assert_eq!(estimator.estimate(1, &pending), Some(32));
+    }
+
+    #[test]
+    fn old_confirmations_are_forgotten() {
+        let mut estimator = FeeEstimator::new();
+        estimator.record_block(10, &[(40, 9), (40, 9), (40, 9)]);
+        estimator.record_block(10 + HISTORY_BLOCKS, &[]);
+        assert_eq!(estimator.estimate(1, &[]), None);
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::p2p::InvItem;
+
+// Hops an announcement travels at most
+pub const DEFAULT_TTL: u8 = 8;
+// Bound of messages remembered
+const MAX_SEEN: usize = 10_000;
+// Seconds a message is remembered once seen
+const SEEN_EXPIRY: i64 = 10 * 60;
+// Seconds a peer has to deliver requested data before it's requested from another
+const REQUEST_TIMEOUT: i64 = 30;
+// Bound of other peers which announced a request, to fall back on
+const MAX_ANNOUNCERS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Seen {
+    time: i64,
+    // Hops left when the message arrived
+    ttl: u8,
+    relayed: bool,
+}
+
+// Messages validated, by the hash of their content, so each is requested and relayed once
+#[derive(Debug, Default)]
+pub struct SeenCache {
+    seen: HashMap<String, Seen>,
+}
+
+impl SeenCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.seen.contains_key(hash)
+    }
+
+    // Remembers the message announced with `ttl` hops left, returning whether it's new
+    pub fn insert(&mut self, hash: &str, ttl: u8, now: i64) -> bool {
+        self.seen.retain(|_, seen| seen.time + SEEN_EXPIRY > now);
+        if self.contains(hash) {
+            return false;
+        }
+        if self.seen.len() >= MAX_SEEN {
+            let oldest = self
+                .seen
+                .iter()
+                .min_by_key(|(_, seen)| seen.time)
+                .map(|(hash, _)| hash.clone());
+            if let Some(oldest) = oldest {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(
+            hash.to_string(),
+            Seen {
+                time: now,
+                ttl,
+                relayed: false,
+            },
+        );
+        true
+    }
+
+    // Marks a message of our own, which is announced with the full TTL
+    pub fn originate(&mut self, hash: &str, now: i64) {
+        self.insert(hash, DEFAULT_TTL, now);
+        if let Some(seen) = self.seen.get_mut(hash) {
+            seen.relayed = true;
+        }
+    }
+
+    // Hops left to relay a validated message over, unless it was relayed
+    // already or ran out of hops
+    pub fn relay(&mut self, hash: &str, now: i64) -> Option<u8> {
+        self.insert(hash, DEFAULT_TTL, now);
+        let seen = self.seen.get_mut(hash)?;
+        if seen.relayed || seen.ttl <= 1 {
+            return None;
+        }
+        seen.relayed = true;
+        Some(seen.ttl - 1)
+    }
+}
+
+#[derive(Debug)]
+struct Request {
+    item: InvItem,
+    // Peer the data was requested from and until when it has to deliver
+    peer: String,
+    deadline: i64,
+    // Other peers which announced the data, oldest first
+    announcers: VecDeque<String>,
+}
+
+// Data requested from peers which hasn't arrived yet, by hash
+#[derive(Debug, Default)]
+pub struct InFlight {
+    requests: HashMap<String, Request>,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records that `peer` announced the item, returning whether to request it from the
+    // peer, which is when it isn't being requested from another one still in time
+    pub fn announce(&mut self, item: &InvItem, peer: &str, now: i64) -> bool {
+        let request = match self.requests.get_mut(&item.hash) {
+            Some(request) => request,
+            None => {
+                let request = Request {
+                    item: item.clone(),
+                    peer: peer.to_string(),
+                    deadline: now + REQUEST_TIMEOUT,
+                    announcers: VecDeque::new(),
+                };
+                self.requests.insert(item.hash.clone(), request);
+                return true;
+            }
+        };
+        if request.deadline <= now {
+            request.peer = peer.to_string();
+            request.deadline = now + REQUEST_TIMEOUT;
+            request.announcers.retain(|announcer| announcer != peer);
+            return true;
+        }
+        if request.peer != peer
+            && !request.announcers.iter().any(|announcer| announcer == peer)
+            && request.announcers.len() < MAX_ANNOUNCERS
+        {
+            request.announcers.push_back(peer.to_string());
+        }
+        false
+    }
+
+    // Hands the requests a peer didn't deliver in time to the next peer which announced
+    // them, returning the items with the peers to request them from. Requests no other
+    // peer announced are dropped
+    pub fn expire(&mut self, now: i64) -> Vec<(InvItem, String)> {
+        let mut reassigned = vec![];
+        self.requests.retain(|_, request| {
+            if request.deadline > now {
+                return true;
+            }
+            match request.announcers.pop_front() {
+                Some(peer) => {
+                    request.peer = peer.clone();
+                    request.deadline = now + REQUEST_TIMEOUT;
+                    reassigned.push((request.item.clone(), peer));
+                    true
+                }
+                None => false,
+            }
+        });
+        reassigned
+    }
+
+    // Forgets the request once its data arrived, returning the item as announced
+    pub fn remove(&mut self, hash: &str) -> Option<InvItem> {
+        self.requests.remove(hash).map(|request| request.item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::InvKind;
+
+    #[test]
+    fn message_is_relayed_once_with_one_hop_less() {
+        // Given
+        let mut cache = SeenCache::new();
+        assert!(cache.insert("hash", 3, 0));
+        assert!(!cache.insert("hash", 3, 0));
+
+        // Then
+        assert_eq!(cache.relay("hash", 0), Some(2));
+        assert_eq!(cache.relay("hash", 0), None);
+    }
+
+    #[test]
+    fn message_out_of_hops_or_our_own_is_not_relayed() {
+        // Given
+        let mut cache = SeenCache::new();
+        cache.insert("last-hop", 1, 0);
+        cache.originate("own", 0);
+
+        // Then
+        assert_eq!(cache.relay("last-hop", 0), None);
+        assert_eq!(cache.relay("own", 0), None);
+        assert_eq!(cache.relay("unannounced", 0), Some(DEFAULT_TTL - 1));
+    }
+
+    #[test]
+    fn seen_messages_expire() {
+        // Given
+        let mut cache = SeenCache::new();
+        cache.insert("old", 3, 0);
+
+        // When
+        let is_new = cache.insert("old", 3, SEEN_EXPIRY);
+
+        // Then
+        assert!(is_new);
+    }
+
+    #[test]
+    fn request_moves_to_next_announcer_after_deadline() {
+        // Given
+        let mut in_flight = InFlight::new();
+        let item = InvItem::new(InvKind::Block, "hash", 3);
+        assert!(in_flight.announce(&item, "a", 0));
+        assert!(!in_flight.announce(&item, "b", 0));
+        assert!(!in_flight.announce(&item, "c", 0));
+        assert!(in_flight.expire(REQUEST_TIMEOUT - 1).is_empty());
+
+        // When
+        let reassigned = in_flight.expire(REQUEST_TIMEOUT);
+
+        // Then
+        assert_eq!(reassigned, vec![(item.clone(), String::from("b"))]);
+        assert!(!in_flight.announce(&item, "a", REQUEST_TIMEOUT));
+
+        // When
+        let next = in_flight.expire(2 * REQUEST_TIMEOUT);
+        let last = in_flight.expire(3 * REQUEST_TIMEOUT);
+        let dropped = in_flight.expire(4 * REQUEST_TIMEOUT);
+
+        // Then
+        assert_eq!(next, vec![(item.clone(), String::from("c"))]);
+        assert_eq!(last, vec![(item.clone(), String::from("a"))]);
+        assert!(dropped.is_empty());
+        assert_eq!(in_flight.remove("hash"), None);
+    }
+
+    #[test]
+    fn expired_request_goes_to_peer_announcing_it_again() {
+        // Given
+        let mut in_flight = InFlight::new();
+        let item = InvItem::new(InvKind::Txn, "hash", 3);
+        in_flight.announce(&item, "a", 0);
+
+        // When
+        let requested = in_flight.announce(&item, "b", REQUEST_TIMEOUT);
+
+        // Then
+        assert!(requested);
+        assert_eq!(in_flight.remove("hash"), Some(item));
+    }
+}
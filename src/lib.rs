@@ -1,12 +1,22 @@
 #[cfg(test)]
 mod testutils;
 
+pub mod address;
 pub mod block;
 pub mod blockchain;
+pub mod clock;
 pub mod error;
+pub mod fees;
+pub mod gossip;
 pub mod hashable;
+pub mod mempool;
+pub mod orphans;
 pub mod p2p;
+pub mod params;
+pub mod policy;
 pub mod repo;
+pub mod script;
+pub mod sync;
 pub mod transaction;
 pub mod wallet;
 
@@ -1,13 +1,23 @@
 #[macro_use]
 extern crate rocket;
 use futures::lock::Mutex;
+use nomadcoin_rs::address::Network;
+use nomadcoin_rs::blockchain::ChainTip;
+use nomadcoin_rs::fees::{DEFAULT_TARGET_BLOCKS, MAX_TARGET_BLOCKS};
+use nomadcoin_rs::mempool::MempoolConfig;
 use nomadcoin_rs::p2p::{
     add_peer_to_peers, broadcast_new_block, broadcast_new_txn, on_p2p_event, P2PMessage, Peer,
     Peers,
 };
+use nomadcoin_rs::params::ChainParams;
+use nomadcoin_rs::policy::Policy;
 use nomadcoin_rs::repo::PickleDBRepository;
-use nomadcoin_rs::{transaction::UTxnOut, Block, BlockChain, Transaction, Wallet};
+use nomadcoin_rs::script::ScriptType;
+use nomadcoin_rs::sync::SyncStatus;
+use nomadcoin_rs::transaction::{Htlc, MultiSig, RelativeLock, TransactionBuilder, UTxnOut};
+use nomadcoin_rs::{Block, BlockChain, Error, Transaction, Wallet};
 use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use rocket::fairing::AdHoc;
 use rocket::http::Status;
 use rocket::response::stream::{Event, EventStream};
 use rocket::serde::{json::Json, Deserialize, Serialize};
@@ -16,9 +26,14 @@ use rocket::tokio::sync::broadcast::{channel, error::RecvError, Sender};
 use rocket::{routes, Shutdown, State};
 use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
+
+// Seconds at least between writes of the mempool to disk
+const MEMPOOL_DUMP_INTERVAL: u64 = 60;
 
 struct AppConfig {
     app_id: String,
+    network: Network,
 }
 
 #[derive(Serialize)]
@@ -34,6 +49,12 @@ struct BalanceRespone {
     balance: u64,
 }
 
+#[derive(Serialize)]
+struct FeeEstimateResponse {
+    target_blocks: u64,
+    fee_rate: u64,
+}
+
 #[derive(Deserialize)]
 struct MineBlockBody {
     address: String,
@@ -44,6 +65,90 @@ struct MakeTransactionBody {
     from: String,
     to: String,
     amount: u64,
+    #[serde(default)]
+    lock_time: u64,
+    #[serde(default)]
+    relative_lock: Option<RelativeLock>,
+    #[serde(default)]
+    replaceable: bool,
+    // Estimated unless given
+    #[serde(default)]
+    fee_rate: Option<u64>,
+}
+
+impl MakeTransactionBody {
+    fn validate(&self, network: Network) -> Result<(), Error> {
+        network.validate(self.from.as_str())?;
+        network.validate(self.to.as_str())
+    }
+
+    fn builder(&self) -> TransactionBuilder {
+        let builder = match &self.relative_lock {
+            Some(relative_lock) => TransactionBuilder::new().locked_output(
+                self.to.as_str(),
+                self.amount,
+                relative_lock.clone(),
+            ),
+            None => TransactionBuilder::new().output(self.to.as_str(), self.amount),
+        };
+        let builder = builder
+            .lock_time(self.lock_time)
+            .replaceable(self.replaceable);
+        match self.fee_rate {
+            Some(fee_rate) => builder.fee_rate(fee_rate),
+            None => builder,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BumpFeeBody {
+    fee_rate: u64,
+}
+
+#[derive(Deserialize)]
+struct MultiSigBody {
+    required: usize,
+    public_keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct MultiSigResponse {
+    address: String,
+    multisig: MultiSig,
+}
+
+#[derive(Deserialize)]
+struct MultiSigTransactionBody {
+    multisig: MultiSig,
+    to: String,
+    amount: u64,
+}
+
+#[derive(Deserialize)]
+struct CreateHtlcBody {
+    from: String,
+    amount: u64,
+    htlc: Htlc,
+}
+
+#[derive(Deserialize)]
+struct ClaimHtlcBody {
+    txn_hash: String,
+    idx: i64,
+    preimage: String,
+}
+
+#[derive(Deserialize)]
+struct RefundHtlcBody {
+    txn_hash: String,
+    idx: i64,
+}
+
+#[derive(Deserialize)]
+struct EmbedDataBody {
+    from: String,
+    data: String,
 }
 
 #[derive(Deserialize)]
@@ -55,23 +160,24 @@ fn url(path: &str) -> String {
     format!("http://localhost:8000{}", path)
 }
 
-fn get_repo() -> PickleDBRepository {
-    let port = std::env::var("ROCKET_PORT").unwrap_or(String::from("8000"));
-    let db_path = format!("blockchain_{}.db", port);
-    let conn = match PickleDb::load(
-        db_path.as_str(),
-        PickleDbDumpPolicy::AutoDump,
-        SerializationMethod::Json,
-    ) {
+fn load_db(db_path: &str, dump_policy: fn() -> PickleDbDumpPolicy) -> std::sync::Mutex<PickleDb> {
+    let conn = match PickleDb::load(db_path, dump_policy(), SerializationMethod::Json) {
         Ok(load) => load,
-        Err(_) => PickleDb::new(
-            db_path.as_str(),
-            PickleDbDumpPolicy::AutoDump,
-            SerializationMethod::Json,
-        ),
+        Err(_) => PickleDb::new(db_path, dump_policy(), SerializationMethod::Json),
     };
-    let conn = std::sync::Mutex::new(conn);
-    return PickleDBRepository::new(conn);
+    std::sync::Mutex::new(conn)
+}
+
+fn get_repo() -> PickleDBRepository {
+    let port = std::env::var("ROCKET_PORT").unwrap_or(String::from("8000"));
+    let conn = load_db(format!("blockchain_{}.db", port).as_str(), || {
+        PickleDbDumpPolicy::AutoDump
+    });
+    // The mempool is also written out on shutdown
+    let mempool_conn = load_db(format!("mempool_{}.db", port).as_str(), || {
+        PickleDbDumpPolicy::PeriodicDump(Duration::from_secs(MEMPOOL_DUMP_INTERVAL))
+    });
+    PickleDBRepository::new(conn, mempool_conn)
 }
 
 #[get("/")]
@@ -92,11 +198,36 @@ async fn documentation() -> Json<Vec<URLDescription>> {
             method: String::from("POST"),
             description: String::from("Add A Block"),
         },
+        URLDescription {
+            url: url("/blocks/template"),
+            method: String::from("GET"),
+            description: String::from("See transactions of the next block, parents first"),
+        },
         URLDescription {
             url: url("/blocks/<hash>"),
             method: String::from("GET"),
             description: String::from("See A Block"),
         },
+        URLDescription {
+            url: url("/blocks/<hash>/invalidate"),
+            method: String::from("POST"),
+            description: String::from("Mark a block invalid, rolling the chain back to its parent"),
+        },
+        URLDescription {
+            url: url("/blocks/<hash>/reconsider"),
+            method: String::from("POST"),
+            description: String::from("Clear the invalid mark of a block"),
+        },
+        URLDescription {
+            url: url("/chain/tips"),
+            method: String::from("GET"),
+            description: String::from("See the tips of all known branches"),
+        },
+        URLDescription {
+            url: url("/sync/status"),
+            method: String::from("GET"),
+            description: String::from("See how far blocks are synced behind the best headers"),
+        },
         URLDescription {
             url: url("/addresses/<address>/txnouts"),
             method: String::from("GET"),
@@ -112,16 +243,86 @@ async fn documentation() -> Json<Vec<URLDescription>> {
             method: String::from("GET"),
             description: String::from("Get transactions inside blockchain memory pool"),
         },
+        URLDescription {
+            url: url("/fees/estimate?target_blocks=<blocks>"),
+            method: String::from("GET"),
+            description: String::from("Estimate the fee rate to be confirmed within blocks"),
+        },
         URLDescription {
             url: url("/transactions"),
             method: String::from("POST"),
             description: String::from("Make a transaction"),
         },
+        URLDescription {
+            url: url("/transactions/build"),
+            method: String::from("POST"),
+            description: String::from("Build and sign a transaction without submitting it"),
+        },
+        URLDescription {
+            url: url("/transactions/submit"),
+            method: String::from("POST"),
+            description: String::from("Submit a signed transaction"),
+        },
+        URLDescription {
+            url: url("/transactions/<hash>/bump-fee"),
+            method: String::from("POST"),
+            description: String::from("Replace a replaceable transaction with a higher fee one"),
+        },
+        URLDescription {
+            url: url("/transactions/sign"),
+            method: String::from("POST"),
+            description: String::from("Add my signature to a transaction"),
+        },
+        URLDescription {
+            url: url("/multisig"),
+            method: String::from("POST"),
+            description: String::from("Create a m-of-n multisig address"),
+        },
+        URLDescription {
+            url: url("/multisig/transactions"),
+            method: String::from("POST"),
+            description: String::from("Build an unsigned transaction spending from a multisig"),
+        },
+        URLDescription {
+            url: url("/htlcs"),
+            method: String::from("POST"),
+            description: String::from("Lock coins in a hash-time-locked contract"),
+        },
+        URLDescription {
+            url: url("/htlcs/claim"),
+            method: String::from("POST"),
+            description: String::from("Claim a HTLC with its preimage"),
+        },
+        URLDescription {
+            url: url("/htlcs/refund"),
+            method: String::from("POST"),
+            description: String::from("Refund a HTLC after its timeout"),
+        },
+        URLDescription {
+            url: url("/transactions/<hash>/preimage"),
+            method: String::from("GET"),
+            description: String::from("Get the preimage revealed by a confirmed HTLC claim"),
+        },
+        URLDescription {
+            url: url("/data"),
+            method: String::from("POST"),
+            description: String::from("Anchor hex encoded data on chain"),
+        },
+        URLDescription {
+            url: url("/data/<payload>/block"),
+            method: String::from("GET"),
+            description: String::from("Get the block anchoring hex encoded data"),
+        },
         URLDescription {
             url: url("/wallet"),
             method: String::from("GET"),
             description: String::from("See my wallet"),
         },
+        URLDescription {
+            url: url("/my-wallet/public-key"),
+            method: String::from("GET"),
+            description: String::from("See my public key, for multisig addresses"),
+        },
         URLDescription {
             url: url("/peers"),
             method: String::from("GET"),
@@ -155,6 +356,9 @@ async fn add_block(
     peers_state: &State<Arc<Mutex<Peers>>>,
     app_config: &State<AppConfig>,
 ) -> Status {
+    if app_config.network.validate(body.address.as_str()).is_err() {
+        return Status::BadRequest;
+    }
     let mut chain = chain_state.lock().await;
     let block = chain.mine_block(body.address.as_str());
     broadcast_new_block(
@@ -166,36 +370,85 @@ async fn add_block(
     Status::Created
 }
 
+#[get("/blocks/template")]
+async fn block_template(chain_state: &State<Arc<Mutex<BlockChain>>>) -> Json<Vec<Transaction>> {
+    let chain = chain_state.lock().await;
+    Json(chain.block_template())
+}
+
 #[get("/blocks/<hash>")]
 async fn get_block(
     chain_state: &State<Arc<Mutex<BlockChain>>>,
     hash: String,
 ) -> Option<Json<Block>> {
     let chain = chain_state.lock().await;
-    match chain.get_block(hash) {
-        Some(block) => Some(Json(block)),
-        None => None,
-    }
+    chain.get_block(hash).map(Json)
+}
+
+#[post("/blocks/<hash>/invalidate")]
+async fn invalidate_block(
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    hash: String,
+) -> Result<Status, (Status, String)> {
+    let mut chain = chain_state.lock().await;
+    chain
+        .invalidate_block(hash.as_str())
+        .map(|_| Status::Ok)
+        .map_err(|err| (Status::BadRequest, err.msg))
+}
+
+#[post("/blocks/<hash>/reconsider")]
+async fn reconsider_block(
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    hash: String,
+) -> Result<Status, (Status, String)> {
+    let mut chain = chain_state.lock().await;
+    chain
+        .reconsider_block(hash.as_str())
+        .map(|_| Status::Ok)
+        .map_err(|err| (Status::BadRequest, err.msg))
+}
+
+#[get("/chain/tips")]
+async fn chain_tips(chain_state: &State<Arc<Mutex<BlockChain>>>) -> Json<Vec<ChainTip>> {
+    let chain = chain_state.lock().await;
+    Json(chain.chain_tips())
+}
+
+#[get("/sync/status")]
+async fn sync_status(chain_state: &State<Arc<Mutex<BlockChain>>>) -> Json<SyncStatus> {
+    let chain = chain_state.lock().await;
+    Json(chain.sync_status())
 }
 
 #[get("/addresses/<address>/txnouts")]
 async fn fetch_txnouts(
     chain_state: &State<Arc<Mutex<BlockChain>>>,
+    app_config: &State<AppConfig>,
     address: String,
-) -> Json<Vec<UTxnOut>> {
+) -> Result<Json<Vec<UTxnOut>>, Status> {
+    app_config
+        .network
+        .validate(address.as_str())
+        .map_err(|_| Status::BadRequest)?;
     let chain = chain_state.lock().await;
     let txnouts = chain.unspent_txnouts_by_address(address.as_str());
-    Json(txnouts)
+    Ok(Json(txnouts))
 }
 
 #[get("/addresses/<address>/balance")]
 async fn get_balance(
     chain_state: &State<Arc<Mutex<BlockChain>>>,
+    app_config: &State<AppConfig>,
     address: String,
-) -> Json<BalanceRespone> {
+) -> Result<Json<BalanceRespone>, Status> {
+    app_config
+        .network
+        .validate(address.as_str())
+        .map_err(|_| Status::BadRequest)?;
     let chain = chain_state.lock().await;
     let balance = chain.balance_by_address(address.as_str());
-    Json(BalanceRespone { address, balance })
+    Ok(Json(BalanceRespone { address, balance }))
 }
 
 #[get("/mempool")]
@@ -204,6 +457,22 @@ async fn mempool(chain_state: &State<Arc<Mutex<BlockChain>>>) -> Json<Vec<Transa
     Json(chain.mempool())
 }
 
+#[get("/fees/estimate?<target_blocks>")]
+async fn estimate_fee(
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    target_blocks: Option<u64>,
+) -> Result<Json<FeeEstimateResponse>, Status> {
+    let target_blocks = target_blocks.unwrap_or(DEFAULT_TARGET_BLOCKS);
+    if target_blocks == 0 || target_blocks > MAX_TARGET_BLOCKS {
+        return Err(Status::BadRequest);
+    }
+    let chain = chain_state.lock().await;
+    Ok(Json(FeeEstimateResponse {
+        target_blocks,
+        fee_rate: chain.estimate_fee(target_blocks),
+    }))
+}
+
 #[post("/transactions", data = "<body>")]
 async fn make_transaction(
     body: Json<MakeTransactionBody>,
@@ -211,14 +480,247 @@ async fn make_transaction(
     peers_state: &State<Arc<Mutex<Peers>>>,
     app_config: &State<AppConfig>,
     wallet: &State<Wallet>,
-) -> Status {
+) -> Result<Status, (Status, String)> {
+    body.validate(app_config.network)
+        .map_err(|err| (Status::BadRequest, err.msg))?;
     let mut chain = chain_state.lock().await;
-    match chain.make_transaction(body.from.as_str(), body.to.as_str(), body.amount, &wallet) {
+    let txn = chain
+        .build_transaction(body.from.as_str(), body.builder())
+        .and_then(|mut txn| {
+            txn.sign(wallet);
+            chain.submit_transaction(txn.clone())?;
+            Ok(txn)
+        });
+    match txn {
         Ok(txn) => {
             broadcast_new_txn(app_config.app_id.clone(), peers_state.inner().clone(), txn).await;
-            Status::Created
+            Ok(Status::Created)
+        }
+        Err(err) => Err((Status::BadRequest, err.msg)),
+    }
+}
+
+#[post("/transactions/build", data = "<body>")]
+async fn build_transaction(
+    body: Json<MakeTransactionBody>,
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    app_config: &State<AppConfig>,
+    wallet: &State<Wallet>,
+) -> Result<Json<Transaction>, Status> {
+    body.validate(app_config.network)
+        .map_err(|_| Status::BadRequest)?;
+    let chain = chain_state.lock().await;
+    match chain.build_transaction(body.from.as_str(), body.builder()) {
+        Ok(mut txn) => {
+            txn.sign(wallet);
+            Ok(Json(txn))
+        }
+        Err(_) => Err(Status::BadRequest),
+    }
+}
+
+#[post("/transactions/submit", data = "<body>")]
+async fn submit_transaction(
+    body: Json<Transaction>,
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    peers_state: &State<Arc<Mutex<Peers>>>,
+    app_config: &State<AppConfig>,
+) -> Result<Status, (Status, String)> {
+    let mut chain = chain_state.lock().await;
+    let txn = body.into_inner();
+    match chain.submit_transaction(txn.clone()) {
+        Ok(_) => {
+            broadcast_new_txn(app_config.app_id.clone(), peers_state.inner().clone(), txn).await;
+            Ok(Status::Created)
+        }
+        Err(err) => Err((Status::BadRequest, err.msg)),
+    }
+}
+
+#[post("/htlcs", data = "<body>")]
+async fn create_htlc(
+    body: Json<CreateHtlcBody>,
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    peers_state: &State<Arc<Mutex<Peers>>>,
+    app_config: &State<AppConfig>,
+    wallet: &State<Wallet>,
+) -> Result<Json<Transaction>, Status> {
+    let network = app_config.network;
+    network
+        .validate(body.from.as_str())
+        .and_then(|_| network.validate(body.htlc.recipient.as_str()))
+        .and_then(|_| network.validate(body.htlc.refund.as_str()))
+        .map_err(|_| Status::BadRequest)?;
+    let mut chain = chain_state.lock().await;
+    let txn = body.htlc.script().and_then(|script| {
+        let builder = TransactionBuilder::new().script_output(script, body.amount);
+        let mut txn = chain.build_transaction(body.from.as_str(), builder)?;
+        txn.sign(wallet);
+        chain.submit_transaction(txn.clone())?;
+        Ok(txn)
+    });
+    match txn {
+        Ok(txn) => {
+            let peers = peers_state.inner().clone();
+            broadcast_new_txn(app_config.app_id.clone(), peers, txn.clone()).await;
+            Ok(Json(txn))
+        }
+        Err(_) => Err(Status::BadRequest),
+    }
+}
+
+#[post("/htlcs/claim", data = "<body>")]
+async fn claim_htlc(
+    body: Json<ClaimHtlcBody>,
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    peers_state: &State<Arc<Mutex<Peers>>>,
+    app_config: &State<AppConfig>,
+    wallet: &State<Wallet>,
+) -> Result<Json<Transaction>, Status> {
+    let mut chain = chain_state.lock().await;
+    let preimage = hex::decode(&body.preimage).map_err(|_| Status::BadRequest)?;
+    match chain.claim_htlc(body.txn_hash.as_str(), body.idx, &preimage, wallet) {
+        Ok(txn) => {
+            let peers = peers_state.inner().clone();
+            broadcast_new_txn(app_config.app_id.clone(), peers, txn.clone()).await;
+            Ok(Json(txn))
+        }
+        Err(_) => Err(Status::BadRequest),
+    }
+}
+
+#[post("/htlcs/refund", data = "<body>")]
+async fn refund_htlc(
+    body: Json<RefundHtlcBody>,
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    peers_state: &State<Arc<Mutex<Peers>>>,
+    app_config: &State<AppConfig>,
+    wallet: &State<Wallet>,
+) -> Result<Json<Transaction>, Status> {
+    let mut chain = chain_state.lock().await;
+    match chain.refund_htlc(body.txn_hash.as_str(), body.idx, wallet) {
+        Ok(txn) => {
+            let peers = peers_state.inner().clone();
+            broadcast_new_txn(app_config.app_id.clone(), peers, txn.clone()).await;
+            Ok(Json(txn))
+        }
+        Err(_) => Err(Status::BadRequest),
+    }
+}
+
+#[get("/transactions/<hash>/preimage")]
+async fn htlc_preimage(
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    hash: String,
+) -> Option<String> {
+    let chain = chain_state.lock().await;
+    chain.htlc_preimage(hash.as_str()).map(hex::encode)
+}
+
+#[post("/data", data = "<body>")]
+async fn embed_data(
+    body: Json<EmbedDataBody>,
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    peers_state: &State<Arc<Mutex<Peers>>>,
+    app_config: &State<AppConfig>,
+    wallet: &State<Wallet>,
+) -> Result<Json<Transaction>, Status> {
+    app_config
+        .network
+        .validate(body.from.as_str())
+        .map_err(|_| Status::BadRequest)?;
+    let mut chain = chain_state.lock().await;
+    let data = hex::decode(&body.data).map_err(|_| Status::BadRequest)?;
+    let builder = TransactionBuilder::new().data_output(&data);
+    let txn = chain
+        .build_transaction(body.from.as_str(), builder)
+        .and_then(|mut txn| {
+            txn.sign(wallet);
+            chain.submit_transaction(txn.clone())?;
+            Ok(txn)
+        });
+    match txn {
+        Ok(txn) => {
+            let peers = peers_state.inner().clone();
+            broadcast_new_txn(app_config.app_id.clone(), peers, txn.clone()).await;
+            Ok(Json(txn))
         }
-        Err(_) => Status::BadRequest,
+        Err(_) => Err(Status::BadRequest),
+    }
+}
+
+#[get("/data/<payload>/block")]
+async fn find_data_block(
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    payload: String,
+) -> Option<Json<Block>> {
+    let chain = chain_state.lock().await;
+    let payload = hex::decode(payload).ok()?;
+    chain.find_data_block(&payload).map(Json)
+}
+
+#[post("/transactions/<hash>/bump-fee", data = "<body>")]
+async fn bump_fee(
+    hash: String,
+    body: Json<BumpFeeBody>,
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    peers_state: &State<Arc<Mutex<Peers>>>,
+    app_config: &State<AppConfig>,
+    wallet: &State<Wallet>,
+) -> Result<Json<Transaction>, Status> {
+    let mut chain = chain_state.lock().await;
+    match chain.bump_fee(hash.as_str(), body.fee_rate, wallet) {
+        Ok(txn) => {
+            let peers = peers_state.inner().clone();
+            broadcast_new_txn(app_config.app_id.clone(), peers, txn.clone()).await;
+            Ok(Json(txn))
+        }
+        Err(_) => Err(Status::BadRequest),
+    }
+}
+
+#[post("/transactions/sign", data = "<body>")]
+async fn sign_transaction(body: Json<Transaction>, wallet: &State<Wallet>) -> Json<Transaction> {
+    let mut txn = body.into_inner();
+    txn.sign(wallet);
+    Json(txn)
+}
+
+#[post("/multisig", data = "<body>")]
+async fn create_multisig(
+    body: Json<MultiSigBody>,
+    app_config: &State<AppConfig>,
+) -> Result<Json<MultiSigResponse>, Status> {
+    let body = body.into_inner();
+    let multisig =
+        MultiSig::new(body.required, body.public_keys).map_err(|_| Status::BadRequest)?;
+    let address = multisig
+        .address(app_config.network)
+        .map_err(|_| Status::BadRequest)?;
+    Ok(Json(MultiSigResponse { address, multisig }))
+}
+
+#[post("/multisig/transactions", data = "<body>")]
+async fn build_multisig_transaction(
+    body: Json<MultiSigTransactionBody>,
+    chain_state: &State<Arc<Mutex<BlockChain>>>,
+    app_config: &State<AppConfig>,
+) -> Result<Json<Transaction>, Status> {
+    let network = app_config.network;
+    network
+        .validate(body.to.as_str())
+        .map_err(|_| Status::BadRequest)?;
+    let body = body.into_inner();
+    let multisig = MultiSig::new(body.multisig.required, body.multisig.public_keys)
+        .map_err(|_| Status::BadRequest)?;
+    let address = multisig.address(network).map_err(|_| Status::BadRequest)?;
+    let chain = chain_state.lock().await;
+    let builder = TransactionBuilder::new()
+        .output(body.to.as_str(), body.amount)
+        .multisig(multisig);
+    match chain.build_transaction(address.as_str(), builder) {
+        Ok(txn) => Ok(Json(txn)),
+        Err(_) => Err(Status::BadRequest),
     }
 }
 
@@ -227,7 +729,13 @@ async fn my_wallet(wallet: &State<Wallet>) -> String {
     wallet.address.clone()
 }
 
+#[get("/my-wallet/public-key")]
+async fn my_public_key(wallet: &State<Wallet>) -> String {
+    hex::encode(wallet.public_key())
+}
+
 #[get("/sse?<openport>")]
+#[allow(clippy::too_many_arguments)]
 async fn sse_get(
     chain_state: &State<Arc<Mutex<BlockChain>>>,
     peers_state: &State<Arc<Mutex<Peers>>>,
@@ -335,16 +843,72 @@ async fn get_peer_id_from_address(address: &str) -> String {
         .unwrap()
 }
 
+fn get_mempool_config() -> MempoolConfig {
+    let default = MempoolConfig::default();
+    let var = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+    };
+    MempoolConfig {
+        max_size: var("MEMPOOL_MAX_SIZE").unwrap_or(default.max_size as u64) as usize,
+        expiry: var("MEMPOOL_EXPIRY").map_or(default.expiry, |expiry| expiry as i64),
+    }
+}
+
+// Checkpoints given as `height:hash,...`
+fn get_chain_params() -> ChainParams {
+    let mut params = ChainParams::default();
+    if let Ok(checkpoints) = std::env::var("CHECKPOINTS") {
+        for checkpoint in checkpoints.split(',') {
+            let (height, hash) = checkpoint
+                .split_once(':')
+                .expect("CHECKPOINTS must be height:hash pairs");
+            let height = height.parse().expect("Checkpoint height must be a number");
+            params.checkpoints.insert(height, hash.to_string());
+        }
+    }
+    params.assume_valid = std::env::var("ASSUME_VALID").is_ok_and(|value| value == "true");
+    params
+}
+
+fn get_policy() -> Policy {
+    let default = Policy::default();
+    let var = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+    };
+    Policy {
+        dust_threshold: var("DUST_THRESHOLD").unwrap_or(default.dust_threshold),
+        max_txn_size: var("MAX_TXN_SIZE").unwrap_or(default.max_txn_size as u64) as usize,
+        max_txn_ins: var("MAX_TXN_INS").unwrap_or(default.max_txn_ins as u64) as usize,
+        max_txn_outs: var("MAX_TXN_OUTS").unwrap_or(default.max_txn_outs as u64) as usize,
+        non_standard: std::env::var("NON_STANDARD_OUTPUTS")
+            .ok()
+            .map_or(default.non_standard, |types| {
+                types.split(',').filter_map(ScriptType::from_name).collect()
+            }),
+        min_relay_fee_rate: var("MIN_RELAY_FEE_RATE").unwrap_or(default.min_relay_fee_rate),
+    }
+}
+
 #[launch]
 fn rocket() -> _ {
+    let network = std::env::var("NETWORK").unwrap_or_else(|_| String::from("mainnet"));
+    let network = Network::from_name(network.as_str()).expect("NETWORK must be mainnet or testnet");
     let repo = Box::new(get_repo());
-    let chain = Arc::new(Mutex::new(BlockChain::load(repo)));
+    let mut chain = BlockChain::load(repo);
+    chain.set_params(get_chain_params());
+    chain.set_mempool_config(get_mempool_config());
+    chain.set_policy(get_policy());
+    let chain = Arc::new(Mutex::new(chain));
     let queue = channel::<P2PMessage>(1024).0;
     let peers = Arc::new(Mutex::new(Peers::new()));
     let app_id = uuid::Uuid::new_v4().to_string();
     let wallet_path = std::env::var("WALLET_PATH").expect("WALLET_PATH must be set");
-    let wallet = Wallet::get(wallet_path.as_str());
-    let app_config = AppConfig { app_id };
+    let wallet = Wallet::get(wallet_path.as_str(), network);
+    let app_config = AppConfig { app_id, network };
 
     rocket::build()
         .mount(
@@ -353,12 +917,31 @@ fn rocket() -> _ {
                 documentation,
                 add_block,
                 fetch_blocks,
+                block_template,
                 get_block,
+                invalidate_block,
+                reconsider_block,
+                chain_tips,
+                sync_status,
                 fetch_txnouts,
                 get_balance,
                 mempool,
+                estimate_fee,
                 make_transaction,
+                build_transaction,
+                submit_transaction,
+                bump_fee,
+                sign_transaction,
+                create_multisig,
+                build_multisig_transaction,
+                create_htlc,
+                claim_htlc,
+                refund_htlc,
+                htlc_preimage,
+                embed_data,
+                find_data_block,
                 my_wallet,
+                my_public_key,
                 sse_get,
                 sse_post,
                 peers,
@@ -366,6 +949,15 @@ fn rocket() -> _ {
                 app_id
             ],
         )
+        .attach(AdHoc::on_shutdown("Dump mempool", |rocket| {
+            Box::pin(async move {
+                if let Some(chain) = rocket.state::<Arc<Mutex<BlockChain>>>() {
+                    if let Err(err) = chain.lock().await.dump_mempool() {
+                        println!("{}", err.msg);
+                    }
+                }
+            })
+        }))
         .manage(chain)
         .manage(queue)
         .manage(peers)
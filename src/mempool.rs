@@ -0,0 +1,716 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+};
+
+use crate::Transaction;
+
+// Two weeks
+const DEFAULT_EXPIRY: i64 = 14 * 24 * 60 * 60;
+const DEFAULT_MAX_SIZE: usize = 5_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolConfig {
+    // Bound of the total size of pending transactions in bytes
+    pub max_size: usize,
+    // Seconds a transaction can wait for a block before it's dropped
+    pub expiry: i64,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+            expiry: DEFAULT_EXPIRY,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+pub struct MempoolEntry {
+    pub txn: Transaction,
+    // When the transaction entered the mempool
+    pub time: i64,
+    // Height of the chain at the time
+    #[serde(default)]
+    pub height: u64,
+}
+
+// Fee rate of transactions mined together, like a child paying for its parents
+fn fee_rate(fee: u64, size: usize) -> u64 {
+    fee * 1000 / size.max(1) as u64
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    entry: MempoolEntry,
+    size: usize,
+    // Arrival order
+    seq: u64,
+    // Fee and size of the transaction with its pending ancestors
+    ancestor_fee: u64,
+    ancestor_size: usize,
+    // Fee and size of the transaction with its pending descendants
+    descendant_fee: u64,
+    descendant_size: usize,
+}
+
+impl Entry {
+    fn ancestor_key(&self) -> (u64, Reverse<u64>) {
+        (
+            fee_rate(self.ancestor_fee, self.ancestor_size),
+            Reverse(self.seq),
+        )
+    }
+
+    fn descendant_key(&self) -> (u64, u64) {
+        (
+            fee_rate(self.descendant_fee, self.descendant_size),
+            self.seq,
+        )
+    }
+}
+
+// Pending transactions, indexed by hash, by the outputs they spend and by the fee
+// rates of the packages they make with their ancestors and descendants
+#[derive(Default)]
+pub struct Mempool {
+    config: MempoolConfig,
+    entries: HashMap<String, Entry>,
+    // Hashes in arrival order
+    order: BTreeMap<u64, String>,
+    next_seq: u64,
+    spenders: HashMap<(String, i64), String>,
+    size: usize,
+    // Hashes by the fee rate with ancestors, earliest arrival last among equal rates
+    by_ancestor_rate: BTreeMap<(u64, Reverse<u64>), String>,
+    // Hashes by the fee rate with descendants, earliest arrival first among equal rates
+    by_descendant_rate: BTreeMap<(u64, u64), String>,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn config(&self) -> &MempoolConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: MempoolConfig) {
+        self.config = config;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get(&self, txn_hash: &str) -> Option<&Transaction> {
+        self.entry(txn_hash).map(|entry| &entry.txn)
+    }
+
+    pub fn entry(&self, txn_hash: &str) -> Option<&MempoolEntry> {
+        self.entries.get(txn_hash).map(|entry| &entry.entry)
+    }
+
+    // Pending transaction spending the output, if any
+    pub fn spender(&self, txn_hash: &str, idx: i64) -> Option<&Transaction> {
+        self.spenders
+            .get(&(txn_hash.to_string(), idx))
+            .and_then(|spender| self.get(spender))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.order
+            .values()
+            .filter_map(|txn_hash| self.get(txn_hash))
+    }
+
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.iter().cloned().collect()
+    }
+
+    // Entries in arrival order, so that parents come before their children
+    pub fn entries(&self) -> Vec<MempoolEntry> {
+        self.order
+            .values()
+            .map(|txn_hash| self.entries[txn_hash].entry.clone())
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.spenders.clear();
+        self.size = 0;
+        self.by_ancestor_rate.clear();
+        self.by_descendant_rate.clear();
+    }
+
+    pub fn insert(&mut self, txn: Transaction, time: i64, height: u64) {
+        if self.entries.contains_key(&txn.hash) {
+            return;
+        }
+        let ancestors = self.ancestors_of(&txn);
+        let descendants = self.descendants_of(&[&txn]);
+        for txn_in in txn.txn_ins.iter() {
+            self.spenders
+                .insert((txn_in.txn_hash.clone(), txn_in.idx), txn.hash.clone());
+        }
+        let size = txn.size();
+        let fee = txn.fee();
+        let (ancestor_fee, ancestor_size) = ancestors.iter().fold((fee, size), |(f, s), hash| {
+            let ancestor = &self.entries[hash];
+            (f + ancestor.entry.txn.fee(), s + ancestor.size)
+        });
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.size += size;
+        self.order.insert(seq, txn.hash.clone());
+        let hash = txn.hash.clone();
+        let entry = Entry {
+            entry: MempoolEntry { txn, time, height },
+            size,
+            seq,
+            ancestor_fee,
+            ancestor_size,
+            descendant_fee: fee,
+            descendant_size: size,
+        };
+        self.by_ancestor_rate
+            .insert(entry.ancestor_key(), hash.clone());
+        self.by_descendant_rate
+            .insert(entry.descendant_key(), hash.clone());
+        self.entries.insert(hash.clone(), entry);
+        if descendants.is_empty() {
+            for ancestor in ancestors.iter() {
+                self.update_package(ancestor, |entry| {
+                    entry.descendant_fee += fee;
+                    entry.descendant_size += size;
+                });
+            }
+            return;
+        }
+        // The transaction arrived after its children, like when a block is disconnected,
+        // which changes packages on both sides in ways easier to recompute
+        let mut affected = ancestors;
+        affected.extend(descendants);
+        affected.push(hash);
+        self.update_packages(&affected);
+    }
+
+    pub fn remove(&mut self, txn_hash: &str) -> Option<Transaction> {
+        self.remove_all(&[txn_hash.to_string()]).pop()
+    }
+
+    // Removes the transaction along with the ones depending on it
+    pub fn remove_with_descendants(&mut self, txn_hash: &str) -> Vec<Transaction> {
+        if !self.entries.contains_key(txn_hash) {
+            return vec![];
+        }
+        let mut hashes = vec![txn_hash.to_string()];
+        hashes.extend(self.descendant_hashes(&hashes));
+        self.remove_all(&hashes)
+    }
+
+    // Removes the transactions, taking them out of the packages of the ones left
+    fn remove_all(&mut self, hashes: &[String]) -> Vec<Transaction> {
+        let removing: HashSet<&String> = hashes.iter().collect();
+        for hash in hashes.iter() {
+            let (fee, size) = match self.entries.get(hash) {
+                Some(entry) => (entry.entry.txn.fee(), entry.size),
+                None => continue,
+            };
+            for ancestor in self.ancestor_hashes(hash) {
+                if !removing.contains(&ancestor) {
+                    self.update_package(&ancestor, |entry| {
+                        entry.descendant_fee -= fee;
+                        entry.descendant_size -= size;
+                    });
+                }
+            }
+            for descendant in self.descendant_hashes(std::slice::from_ref(hash)) {
+                if !removing.contains(&descendant) {
+                    self.update_package(&descendant, |entry| {
+                        entry.ancestor_fee -= fee;
+                        entry.ancestor_size -= size;
+                    });
+                }
+            }
+        }
+        let mut removed = vec![];
+        for hash in hashes.iter() {
+            let entry = match self.entries.remove(hash) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            for txn_in in entry.entry.txn.txn_ins.iter() {
+                self.spenders.remove(&(txn_in.txn_hash.clone(), txn_in.idx));
+            }
+            self.size -= entry.size;
+            self.order.remove(&entry.seq);
+            self.by_ancestor_rate.remove(&entry.ancestor_key());
+            self.by_descendant_rate.remove(&entry.descendant_key());
+            removed.push(entry.entry.txn);
+        }
+        removed
+    }
+
+    // Changes the package fees and sizes of the transaction, keeping it in place
+    // in the fee rate indexes
+    fn update_package(&mut self, hash: &str, update: impl FnOnce(&mut Entry)) {
+        let entry = match self.entries.get_mut(hash) {
+            Some(entry) => entry,
+            None => return,
+        };
+        self.by_ancestor_rate.remove(&entry.ancestor_key());
+        self.by_descendant_rate.remove(&entry.descendant_key());
+        update(entry);
+        self.by_ancestor_rate
+            .insert(entry.ancestor_key(), hash.to_string());
+        self.by_descendant_rate
+            .insert(entry.descendant_key(), hash.to_string());
+    }
+
+    // Recomputes the fees and sizes of the packages the transactions make
+    // with their ancestors and descendants
+    fn update_packages(&mut self, hashes: &[String]) {
+        let mut done: HashSet<&String> = HashSet::new();
+        for hash in hashes.iter() {
+            if !done.insert(hash) || !self.entries.contains_key(hash) {
+                continue;
+            }
+            let sum = |hashes: &[String]| {
+                hashes.iter().fold((0, 0), |(fee, size), hash| {
+                    let entry = &self.entries[hash];
+                    (fee + entry.entry.txn.fee(), size + entry.size)
+                })
+            };
+            let (ancestor_fee, ancestor_size) = sum(&self.ancestor_hashes(hash));
+            let (descendant_fee, descendant_size) =
+                sum(&self.descendant_hashes(std::slice::from_ref(hash)));
+            self.update_package(hash, |entry| {
+                let (fee, size) = (entry.entry.txn.fee(), entry.size);
+                entry.ancestor_fee = fee + ancestor_fee;
+                entry.ancestor_size = size + ancestor_size;
+                entry.descendant_fee = fee + descendant_fee;
+                entry.descendant_size = size + descendant_size;
+            });
+        }
+    }
+
+    // Hashes of the pending transactions the transaction spends from, directly or not,
+    // parents first
+    fn ancestor_hashes(&self, txn_hash: &str) -> Vec<String> {
+        match self.get(txn_hash) {
+            Some(txn) => self.ancestors_of(txn),
+            None => vec![],
+        }
+    }
+
+    fn ancestors_of(&self, txn: &Transaction) -> Vec<String> {
+        let mut visited: HashSet<&str> = HashSet::new();
+        let mut ancestors = vec![];
+        // Transactions with whether their parents have been pushed already
+        let mut stack: Vec<(&Transaction, bool)> = vec![(txn, false)];
+        while let Some((txn, expanded)) = stack.pop() {
+            if expanded {
+                ancestors.push(txn.hash.clone());
+                continue;
+            }
+            stack.push((txn, true));
+            for parent in self.parents(txn).into_iter().rev() {
+                if visited.insert(&parent.hash) {
+                    stack.push((parent, false));
+                }
+            }
+        }
+        // The transaction itself comes last
+        ancestors.pop();
+        ancestors
+    }
+
+    fn parents<'a>(&'a self, txn: &Transaction) -> Vec<&'a Transaction> {
+        txn.txn_ins
+            .iter()
+            .filter_map(|txn_in| self.get(&txn_in.txn_hash))
+            .collect()
+    }
+
+    // Hashes of the pending transactions spending from any of `txns`,
+    // directly or not, children before grandchildren
+    fn descendants_of(&self, txns: &[&Transaction]) -> Vec<String> {
+        let mut visited: HashSet<&str> = txns.iter().map(|txn| txn.hash.as_str()).collect();
+        let mut descendants = vec![];
+        let mut queue: VecDeque<&Transaction> = txns.iter().copied().collect();
+        while let Some(txn) = queue.pop_front() {
+            for idx in 0..txn.txn_outs.len() {
+                let child = match self.spender(&txn.hash, idx as i64) {
+                    Some(child) => child,
+                    None => continue,
+                };
+                if visited.insert(&child.hash) {
+                    descendants.push(child.hash.clone());
+                    queue.push_back(child);
+                }
+            }
+        }
+        descendants
+    }
+
+    fn descendant_hashes(&self, txn_hashes: &[String]) -> Vec<String> {
+        let txns: Vec<&Transaction> = txn_hashes
+            .iter()
+            .filter_map(|hash| self.get(hash))
+            .collect();
+        self.descendants_of(&txns)
+    }
+
+    fn transactions_of(&self, hashes: &[String]) -> Vec<Transaction> {
+        hashes
+            .iter()
+            .filter_map(|hash| self.get(hash).cloned())
+            .collect()
+    }
+
+    // Pending transactions which `txn` spends from, directly or not, parents first
+    pub fn ancestors(&self, txn: &Transaction) -> Vec<Transaction> {
+        self.transactions_of(&self.ancestors_of(txn))
+    }
+
+    // Number of pending transactions `txn` spends from, directly or not,
+    // counting no further than `limit`
+    pub fn count_ancestors(&self, txn: &Transaction, limit: usize) -> usize {
+        let mut visited: HashSet<&str> = HashSet::new();
+        let mut stack = vec![txn];
+        while let Some(txn) = stack.pop() {
+            for parent in self.parents(txn) {
+                if visited.insert(&parent.hash) {
+                    if visited.len() >= limit {
+                        return limit;
+                    }
+                    stack.push(parent);
+                }
+            }
+        }
+        visited.len()
+    }
+
+    // Pending transactions spending from `txn`, directly or not
+    pub fn descendants(&self, txn: &Transaction) -> Vec<Transaction> {
+        self.transactions_of(&self.descendants_of(&[txn]))
+    }
+
+    // Pending transactions spending any of the outputs `txn` spends,
+    // along with their descendants, which would be evicted with them
+    pub fn conflicts(&self, txn: &Transaction) -> Vec<Transaction> {
+        let conflicts: Vec<String> = txn
+            .txn_ins
+            .iter()
+            .filter_map(|txn_in| self.spenders.get(&(txn_in.txn_hash.clone(), txn_in.idx)))
+            .cloned()
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect();
+        let mut hashes: Vec<String> = self
+            .order
+            .values()
+            .filter(|hash| conflicts.contains(hash))
+            .cloned()
+            .collect();
+        hashes.extend(self.descendant_hashes(&hashes));
+        self.transactions_of(&hashes)
+    }
+
+    // Drops transactions which have been waiting longer than the expiry
+    pub fn expire(&mut self, now: i64) -> Vec<Transaction> {
+        let expired: Vec<String> = self
+            .order
+            .values()
+            .filter(|txn_hash| now - self.entries[*txn_hash].entry.time > self.config.expiry)
+            .cloned()
+            .collect();
+        expired
+            .iter()
+            .flat_map(|txn_hash| self.remove_with_descendants(txn_hash))
+            .collect()
+    }
+
+    // Evicts the transactions paying the lowest fee rates, counting their descendants,
+    // until the mempool fits in its size limit
+    pub fn trim(&mut self) -> Vec<MempoolEntry> {
+        let mut evicted = vec![];
+        while self.size > self.config.max_size {
+            let lowest = match self.by_descendant_rate.values().next() {
+                Some(txn_hash) => txn_hash.clone(),
+                None => break,
+            };
+            let mut hashes = vec![lowest];
+            hashes.extend(self.descendant_hashes(&hashes));
+            evicted.extend(hashes.iter().filter_map(|hash| self.entry(hash).cloned()));
+            self.remove_all(&hashes);
+        }
+        evicted
+    }
+
+    // Orders transactions by the fee rate of the packages they make with their pending
+    // ancestors, which always come first, leaving out packages beyond `max_size` bytes
+    pub fn block_template(&self, max_size: usize) -> Vec<Transaction> {
+        // Package fees and sizes less the ancestors already included
+        let mut packages: HashMap<&str, (u64, usize)> = HashMap::new();
+        let mut candidates = self.by_ancestor_rate.clone();
+        let mut included: HashSet<String> = HashSet::new();
+        let mut txns = vec![];
+        let mut template_size = 0;
+        while let Some((_, txn_hash)) = candidates.pop_last() {
+            if included.contains(&txn_hash) {
+                continue;
+            }
+            let entry = &self.entries[&txn_hash];
+            let (_, package_size) = packages
+                .get(txn_hash.as_str())
+                .copied()
+                .unwrap_or((entry.ancestor_fee, entry.ancestor_size));
+            if template_size + package_size > max_size {
+                // Smaller packages paying less may still fit
+                continue;
+            }
+            let mut package: Vec<String> = self
+                .ancestor_hashes(&txn_hash)
+                .into_iter()
+                .filter(|ancestor| !included.contains(ancestor))
+                .collect();
+            package.push(txn_hash);
+            template_size += package_size;
+            included.extend(package.iter().cloned());
+            // Descendants of the package no longer pay for what it included
+            for hash in package.iter() {
+                let entry = &self.entries[hash];
+                for descendant in self.descendant_hashes(std::slice::from_ref(hash)) {
+                    if included.contains(&descendant) {
+                        continue;
+                    }
+                    let (key, descendant) = self.entries.get_key_value(&descendant).unwrap();
+                    let (fee, size) = packages
+                        .get(key.as_str())
+                        .copied()
+                        .unwrap_or((descendant.ancestor_fee, descendant.ancestor_size));
+                    let old_key = (fee_rate(fee, size), Reverse(descendant.seq));
+                    let (fee, size) = (fee - entry.entry.txn.fee(), size - entry.size);
+                    candidates.remove(&old_key);
+                    candidates.insert((fee_rate(fee, size), Reverse(descendant.seq)), key.clone());
+                    packages.insert(key.as_str(), (fee, size));
+                }
+            }
+            txns.extend(self.transactions_of(&package));
+        }
+        txns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testutils,
+        transaction::{TxnIn, TxnOut},
+    };
+
+    // Spends `amount` from the first output of `parent`, paying `fee`
+    fn spend(parent: &str, amount: u64, fee: u64) -> Transaction {
+        Transaction::new(
+            vec![TxnIn::new(parent, 0, amount)],
+            vec![TxnOut::new(&testutils::address("to"), amount - fee)],
+        )
+    }
+
+    #[test]
+    fn mempool_indexes_transactions_by_spent_outputs() {
+        // Given
+        let mut mempool = Mempool::default();
+        let parent = spend("confirmed", 50, 1);
+        let child = spend(&parent.hash, 49, 1);
+
+        // When
+        mempool.insert(parent.clone(), 0, 0);
+        mempool.insert(child.clone(), 0, 0);
+
+        // Then
+        assert_eq!(mempool.spender("confirmed", 0), Some(&parent));
+        assert_eq!(mempool.spender(&parent.hash, 0), Some(&child));
+        assert_eq!(mempool.ancestors(&child), vec![parent.clone()]);
+        assert_eq!(mempool.descendants(&parent), vec![child.clone()]);
+        assert_eq!(mempool.size(), parent.size() + child.size());
+
+        // When
+        let removed = mempool.remove_with_descendants(&parent.hash);
+
+        // Then
+        assert_eq!(removed, vec![parent, child]);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.spender("confirmed", 0), None);
+        assert_eq!(mempool.size(), 0);
+    }
+
+    #[test]
+    fn expired_transactions_are_dropped_with_descendants() {
+        // Given
+        let mut mempool = Mempool::default();
+        let old = spend("confirmed", 50, 1);
+        let child = spend(&old.hash, 49, 1);
+        let recent = spend("other", 50, 1);
+        mempool.insert(old.clone(), 0, 0);
+        mempool.insert(child.clone(), DEFAULT_EXPIRY, 0);
+        mempool.insert(recent.clone(), DEFAULT_EXPIRY, 0);
+
+        // When
+        let expired = mempool.expire(DEFAULT_EXPIRY + 1);
+
+        // Then
+        assert_eq!(expired, vec![old, child]);
+        assert_eq!(mempool.transactions(), vec![recent]);
+    }
+
+    #[test]
+    fn block_template_fits_highest_fee_rates_in_max_size() {
+        // Given
+        let mut mempool = Mempool::default();
+        let cheap = spend("cheap", 50, 1);
+        let parent = spend("other", 50, 1);
+        let child = spend(&parent.hash, 49, 40);
+        let expensive = spend("third", 50, 10);
+        for txn in [&cheap, &parent, &child, &expensive] {
+            mempool.insert(txn.clone(), 0, 0);
+        }
+
+        // When
+        let max_size = parent.size() + child.size() + expensive.size();
+        let template = mempool.block_template(max_size);
+
+        // Then
+        assert_eq!(template, vec![parent, child, expensive.clone()]);
+        assert_eq!(mempool.block_template(cheap.size()), vec![expensive]);
+    }
+
+    #[test]
+    fn trim_evicts_lowest_fee_rate_transactions() {
+        // Given
+        let cheap = spend("confirmed", 50, 1);
+        let expensive = spend("other", 50, 10);
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_size: cheap.size() + expensive.size() - 1,
+            ..Default::default()
+        });
+        mempool.insert(cheap.clone(), 0, 0);
+        mempool.insert(expensive.clone(), 0, 0);
+
+        // When
+        let evicted = mempool.trim();
+
+        // Then
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].txn, cheap);
+        assert_eq!(mempool.transactions(), vec![expensive]);
+    }
+
+    // Spends the first output of each parent into a single output, paying `fee`
+    fn merge(parents: &[&Transaction], fee: u64) -> Transaction {
+        let amount: u64 = parents.iter().map(|parent| parent.txn_outs[0].amount).sum();
+        Transaction::new(
+            parents
+                .iter()
+                .map(|parent| TxnIn::new(&parent.hash, 0, parent.txn_outs[0].amount))
+                .collect(),
+            vec![TxnOut::new(&testutils::address("to"), amount - fee)],
+        )
+    }
+
+    // Spends the first output of `parent` into two outputs
+    fn split(parent: &Transaction) -> Transaction {
+        let amount = parent.txn_outs[0].amount;
+        Transaction::new(
+            vec![TxnIn::new(&parent.hash, 0, amount)],
+            vec![
+                TxnOut::new(&testutils::address("to"), amount / 2),
+                TxnOut::new(&testutils::address("to"), amount / 2 - 1),
+            ],
+        )
+    }
+
+    #[test]
+    fn ancestors_of_diamonds_are_walked_once_and_counted_up_to_limit() {
+        // Given
+        let mut mempool = Mempool::default();
+        let mut tip = spend("confirmed", 1_000_000, 1);
+        mempool.insert(tip.clone(), 0, 0);
+        for _ in 0..30 {
+            let fork = split(&tip);
+            let left = Transaction::new(
+                vec![TxnIn::new(&fork.hash, 0, fork.txn_outs[0].amount)],
+                vec![TxnOut::new(
+                    &testutils::address("to"),
+                    fork.txn_outs[0].amount - 1,
+                )],
+            );
+            let right = Transaction::new(
+                vec![TxnIn::new(&fork.hash, 1, fork.txn_outs[1].amount)],
+                vec![TxnOut::new(
+                    &testutils::address("to"),
+                    fork.txn_outs[1].amount - 1,
+                )],
+            );
+            tip = merge(&[&left, &right], 1);
+            for txn in [&fork, &left, &right, &tip] {
+                mempool.insert(txn.clone(), 0, 0);
+            }
+        }
+        let child = spend(&tip.hash, tip.txn_outs[0].amount, 1);
+
+        // When
+        let ancestors = mempool.ancestors(&child);
+
+        // Then
+        assert_eq!(ancestors.len(), 121);
+        assert_eq!(ancestors.last(), Some(&tip));
+        assert_eq!(mempool.count_ancestors(&child, 25), 25);
+        assert_eq!(mempool.descendants(&mempool.transactions()[0]).len(), 120);
+    }
+
+    #[test]
+    fn packages_follow_parents_arriving_after_children() {
+        // Given
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_size: 0,
+            ..Default::default()
+        });
+        let parent = spend("confirmed", 50, 1);
+        let child = spend(&parent.hash, 49, 40);
+        let other = spend("other", 50, 10);
+
+        // When
+        mempool.insert(child.clone(), 0, 0);
+        mempool.insert(parent.clone(), 0, 0);
+        mempool.insert(other.clone(), 0, 0);
+
+        // Then
+        assert_eq!(
+            mempool.block_template(usize::MAX),
+            vec![parent, child, other.clone()]
+        );
+        let evicted = mempool.trim();
+        assert_eq!(evicted[0].txn, other);
+        assert!(mempool.is_empty());
+    }
+}
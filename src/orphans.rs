@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use crate::Block;
+
+// Bound of blocks waiting for their parents
+const MAX_ORPHANS: usize = 100;
+// Bound of orphans kept from one peer, so no peer can fill the pool
+const MAX_ORPHANS_PER_PEER: usize = 32;
+// Seconds an orphan waits for its parent before it's dropped
+const ORPHAN_EXPIRY: i64 = 20 * 60;
+
+// Blocks which arrived before their parents, indexed by the parent hash
+#[derive(Debug, Default)]
+pub struct OrphanPool {
+    // Orphans with the peer they came from and when they arrived, by parent hash
+    blocks: HashMap<String, Vec<(Block, String, i64)>>,
+}
+
+impl OrphanPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.values().map(|orphans| orphans.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Block> {
+        self.blocks
+            .values()
+            .flatten()
+            .map(|(block, _, _)| block)
+            .find(|block| block.hash == hash)
+    }
+
+    // Keeps the block, dropping the oldest orphan of the peer when it sent too many,
+    // or else the oldest orphan when the pool is full
+    pub fn insert(&mut self, block: Block, peer: &str, time: i64) {
+        if self.get(&block.hash).is_some() {
+            return;
+        }
+        let from_peer = self
+            .blocks
+            .values()
+            .flatten()
+            .filter(|(_, from, _)| from == peer);
+        if from_peer.count() >= MAX_ORPHANS_PER_PEER {
+            if let Some(oldest) = self.oldest(|from| from == peer) {
+                self.remove(&oldest);
+            }
+        } else if self.len() >= MAX_ORPHANS {
+            if let Some(oldest) = self.oldest(|_| true) {
+                self.remove(&oldest);
+            }
+        }
+        self.blocks
+            .entry(block.prev_hash.clone())
+            .or_default()
+            .push((block, peer.to_string(), time));
+    }
+
+    // Takes the orphans which build on `prev_hash` out of the pool
+    pub fn take_children(&mut self, prev_hash: &str) -> Vec<Block> {
+        self.blocks
+            .remove(prev_hash)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(block, _, _)| block)
+            .collect()
+    }
+
+    pub fn expire(&mut self, now: i64) {
+        for orphans in self.blocks.values_mut() {
+            orphans.retain(|(_, _, time)| time + ORPHAN_EXPIRY > now);
+        }
+        self.blocks.retain(|_, orphans| !orphans.is_empty());
+    }
+
+    // Hash of the oldest orphan from a peer `from_peer` accepts
+    fn oldest(&self, from_peer: impl Fn(&str) -> bool) -> Option<String> {
+        self.blocks
+            .values()
+            .flatten()
+            .filter(|(_, from, _)| from_peer(from))
+            .min_by_key(|(_, _, time)| *time)
+            .map(|(block, _, _)| block.hash.clone())
+    }
+
+    fn remove(&mut self, hash: &str) {
+        for orphans in self.blocks.values_mut() {
+            orphans.retain(|(block, _, _)| block.hash != hash);
+        }
+        self.blocks.retain(|_, orphans| !orphans.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orphan(hash: &str, prev_hash: &str) -> Block {
+        Block {
+            hash: hash.to_string(),
+            prev_hash: prev_hash.to_string(),
+            height: 2,
+            difficulty: 1,
+            nonce: 0,
+            timestamp: 0,
+            transactions: vec![],
+        }
+    }
+
+    #[test]
+    fn children_are_taken_by_parent_hash() {
+        // Given
+        let mut pool = OrphanPool::new();
+        pool.insert(orphan("a", "parent"), "peer", 0);
+        pool.insert(orphan("b", "parent"), "peer", 0);
+        pool.insert(orphan("c", "other"), "peer", 0);
+        pool.insert(orphan("a", "parent"), "peer", 0);
+
+        // When
+        let children = pool.take_children("parent");
+
+        // Then
+        assert_eq!(children, vec![orphan("a", "parent"), orphan("b", "parent")]);
+        assert_eq!(pool.len(), 1);
+        assert!(pool.get("c").is_some());
+    }
+
+    #[test]
+    fn oldest_orphan_is_dropped_when_full() {
+        // Given
+        let mut pool = OrphanPool::new();
+        for time in 0..MAX_ORPHANS as i64 {
+            pool.insert(orphan(&time.to_string(), "parent"), &time.to_string(), time);
+        }
+
+        // When
+        pool.insert(orphan("new", "parent"), "peer", MAX_ORPHANS as i64);
+
+        // Then
+        assert_eq!(pool.len(), MAX_ORPHANS);
+        assert!(pool.get("0").is_none());
+        assert!(pool.get("new").is_some());
+    }
+
+    #[test]
+    fn orphans_expire_by_age() {
+        // Given
+        let mut pool = OrphanPool::new();
+        pool.insert(orphan("old", "parent"), "peer", 0);
+        pool.insert(orphan("new", "other"), "peer", 10);
+
+        // When
+        pool.expire(ORPHAN_EXPIRY);
+
+        // Then
+        assert!(pool.get("old").is_none());
+        assert!(pool.get("new").is_some());
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn oldest_orphan_of_peer_is_dropped_when_it_sent_too_many() {
+        // Given
+        let mut pool = OrphanPool::new();
+        pool.insert(orphan("other", "parent"), "other", 0);
+        for time in 1..=MAX_ORPHANS_PER_PEER as i64 {
+            pool.insert(orphan(&time.to_string(), "parent"), "peer", time);
+        }
+
+        // When
+        pool.insert(orphan("new", "parent"), "peer", 100);
+
+        // Then
+        assert_eq!(pool.len(), MAX_ORPHANS_PER_PEER + 1);
+        assert!(pool.get("1").is_none());
+        assert!(pool.get("other").is_some());
+        assert!(pool.get("new").is_some());
+    }
+}
@@ -1,20 +1,91 @@
+use chrono::Utc;
 use futures::lock::Mutex as FutureMutex;
 use futures::stream::StreamExt;
 use reqwest_eventsource::{Event, EventSource};
 use rocket::serde::json::serde_json;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc, thread, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
-use crate::{Block, BlockChain, Transaction};
+use crate::{
+    block::BlockHeader,
+    clock::MAX_OFFSET,
+    gossip::{InFlight, SeenCache, DEFAULT_TTL},
+    sync::MAX_HEADERS,
+    Block, BlockChain, Transaction,
+};
+
+// Bound of hashes remembered per peer
+const MAX_KNOWN_INVENTORY: usize = 5000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum InvKind {
+    Block,
+    Txn,
+}
+
+fn default_ttl() -> u8 {
+    DEFAULT_TTL
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct InvItem {
+    pub kind: InvKind,
+    pub hash: String,
+    // Hops the announcement can still be relayed over
+    #[serde(default = "default_ttl")]
+    pub ttl: u8,
+}
+
+impl InvItem {
+    pub fn new(kind: InvKind, hash: &str, ttl: u8) -> Self {
+        Self {
+            kind,
+            hash: hash.to_string(),
+            ttl,
+        }
+    }
+}
+
+// Hashes a peer is known to have, forgetting the oldest ones
+#[derive(Debug, Default)]
+struct KnownInventory {
+    hashes: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl KnownInventory {
+    fn insert(&mut self, hash: &str) {
+        if !self.hashes.insert(hash.to_string()) {
+            return;
+        }
+        self.order.push_back(hash.to_string());
+        if self.order.len() > MAX_KNOWN_INVENTORY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+    }
+}
 
 pub struct Peers {
     map: HashMap<String, Peer>,
+    known: HashMap<String, KnownInventory>,
+    seen: SeenCache,
+    in_flight: InFlight,
 }
 
 impl Peers {
     pub fn new() -> Peers {
         Peers {
             map: HashMap::new(),
+            known: HashMap::new(),
+            seen: SeenCache::new(),
+            in_flight: InFlight::new(),
         }
     }
     pub fn add(&mut self, peer: &Peer) {
@@ -22,6 +93,19 @@ impl Peers {
     }
     pub fn remove(&mut self, address: &str) {
         self.map.remove(address);
+        self.known.remove(address);
+    }
+    // Remembers the peer has the block or transaction, so it isn't announced to it
+    pub fn mark_known(&mut self, address: &str, hash: &str) {
+        self.known
+            .entry(address.to_string())
+            .or_default()
+            .insert(hash);
+    }
+    pub fn knows(&self, address: &str, hash: &str) -> bool {
+        self.known
+            .get(address)
+            .is_some_and(|known| known.hashes.contains(hash))
     }
     pub fn contains(&self, address: &str) -> bool {
         self.map.contains_key(address)
@@ -31,6 +115,12 @@ impl Peers {
     }
 }
 
+impl Default for Peers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Peer {
     pub id: String,
@@ -72,6 +162,7 @@ pub async fn add_peer_to_peers(
             match event {
                 Ok(Event::Open) => {
                     println!("Connection Open!");
+                    send_time(app_id.clone(), &address).await;
                     send_newest_block(app_id.clone(), &address, newest_block.clone()).await;
                     if should_broadcast {
                         broadcast_new_peer(app_id.clone(), peers.clone(), peer.clone()).await;
@@ -92,11 +183,17 @@ pub async fn add_peer_to_peers(
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum P2PEvent {
     NewestBlockReceived,
-    AllBlocksRequested,
-    AllBlocksRecevied,
+    HeadersRequested,
+    HeadersReceived,
+    BlocksRequested,
+    BlocksReceived,
     NewBlockNotified,
     NewTxnNotified,
     NewPeerNotified,
+    TimeReceived,
+    BlockRequested,
+    InventoryNotified,
+    DataRequested,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -115,13 +212,6 @@ async fn send_message(address: &str, msg: P2PMessage) {
         .unwrap();
 }
 
-async fn broadcast_message(peers: Arc<FutureMutex<Peers>>, msg: P2PMessage) {
-    let peers = peers.lock().await;
-    for address in peers.map.keys() {
-        send_message(address, msg.clone()).await;
-    }
-}
-
 pub async fn on_p2p_event(
     app_id: String,
     chain: &mut BlockChain,
@@ -134,11 +224,17 @@ pub async fn on_p2p_event(
         P2PEvent::NewestBlockReceived => {
             on_newest_block_received(app_id, chain, peer, msg, peers, openport).await;
         }
-        P2PEvent::AllBlocksRequested => {
-            on_all_blocks_requested(app_id, chain, peer, msg, peers, openport).await;
+        P2PEvent::HeadersRequested => {
+            on_headers_requested(app_id, chain, peer, msg, peers, openport).await;
         }
-        P2PEvent::AllBlocksRecevied => {
-            on_all_blocks_received(app_id, chain, peer, msg, peers, openport).await;
+        P2PEvent::HeadersReceived => {
+            on_headers_received(app_id, chain, peer, msg, peers, openport).await;
+        }
+        P2PEvent::BlocksRequested => {
+            on_blocks_requested(app_id, chain, peer, msg, peers, openport).await;
+        }
+        P2PEvent::BlocksReceived => {
+            on_blocks_received(app_id, chain, peer, msg, peers, openport).await;
         }
         P2PEvent::NewBlockNotified => {
             on_new_block_notified(app_id, chain, peer, msg, peers, openport).await;
@@ -149,6 +245,51 @@ pub async fn on_p2p_event(
         P2PEvent::NewPeerNotified => {
             on_new_peer_notified(app_id, chain, peer, msg, peers, openport).await;
         }
+        P2PEvent::TimeReceived => {
+            on_time_received(app_id, chain, peer, msg, peers, openport).await;
+        }
+        P2PEvent::BlockRequested => {
+            on_block_requested(app_id, chain, peer, msg, peers, openport).await;
+        }
+        P2PEvent::InventoryNotified => {
+            on_inventory_notified(app_id, chain, peer, msg, peers, openport).await;
+        }
+        P2PEvent::DataRequested => {
+            on_data_requested(app_id, chain, peer, msg, peers, openport).await;
+        }
+    }
+}
+
+// Peers tell their clocks on connecting, to adjust ours
+async fn send_time(app_id: String, address: &str) {
+    let payload = P2PMessage {
+        event: P2PEvent::TimeReceived,
+        payload: Some(Utc::now().timestamp().to_string()),
+        sender_id: app_id,
+    };
+    send_message(address, payload).await;
+}
+
+async fn on_time_received(
+    _app_id: String,
+    chain: &mut BlockChain,
+    peer: &Peer,
+    msg: &P2PMessage,
+    _peers: Arc<FutureMutex<Peers>>,
+    _openport: u16,
+) {
+    let peer_time = msg
+        .payload
+        .as_ref()
+        .and_then(|payload| payload.parse::<i64>().ok());
+    if let Some(peer_time) = peer_time {
+        chain.add_peer_time(&peer.address, peer_time);
+        if chain.clock().is_out_of_sync() {
+            println!(
+                "Warning: peers disagree with the local clock by over {} seconds, check the system time",
+                MAX_OFFSET
+            );
+        }
     }
 }
 
@@ -189,9 +330,10 @@ async fn on_newest_block_received(
     _openport: u16,
 ) {
     println!("Got newest block from {}", peer.address);
-    let peer_newest_block = msg.payload.as_ref().map_or(None, |payload| {
-        Some(serde_json::from_str::<Block>(payload).unwrap())
-    });
+    let peer_newest_block = msg
+        .payload
+        .as_ref()
+        .map(|payload| serde_json::from_str::<Block>(payload).unwrap());
     let own_newest_block = chain.newest_block();
     if let Some(peer_newest_block) = peer_newest_block {
         // TODO: improve this to send message after all connection is established
@@ -202,18 +344,20 @@ async fn on_newest_block_received(
             || own_newest_block.is_some()
                 && peer_newest_block.height >= own_newest_block.as_ref().unwrap().height
         {
-            request_all_blocks(app_id, &peer.address).await;
+            request_headers(app_id, &peer.address, chain.block_locator()).await;
         } else {
             send_newest_block(app_id, &peer.address, own_newest_block).await;
         }
     }
 }
 
-async fn request_all_blocks(app_id: String, address: &str) {
-    println!("Requesting all blocks from {}", address);
+// Headers are synced first, asking for the ones after where the chains fork,
+// which the peer finds from the block locator
+async fn request_headers(app_id: String, address: &str, locator: Vec<String>) {
+    println!("Requesting headers from {}", address);
     let payload = P2PMessage {
-        event: P2PEvent::AllBlocksRequested,
-        payload: None,
+        event: P2PEvent::HeadersRequested,
+        payload: Some(serde_json::to_string(&locator).unwrap()),
         sender_id: app_id,
     };
     send_message(address, payload).await;
@@ -223,74 +367,273 @@ async fn send_newest_block(app_id: String, address: &str, newest_block: Option<B
     println!("Send newest block to {}", address);
     let payload = P2PMessage {
         event: P2PEvent::NewestBlockReceived,
-        payload: newest_block.map_or(None, |block| Some(serde_json::to_string(&block).unwrap())),
+        payload: newest_block.map(|block| serde_json::to_string(&block).unwrap()),
         sender_id: app_id,
     };
     send_message(address, payload).await;
 }
 
-async fn on_all_blocks_requested(
+async fn on_headers_requested(
     app_id: String,
     chain: &mut BlockChain,
     peer: &Peer,
-    _msg: &P2PMessage,
+    msg: &P2PMessage,
     _peers: Arc<FutureMutex<Peers>>,
     _openport: u16,
 ) {
-    println!("All blocks requested from {}", peer.address);
-    let blocks = chain.all_blocks();
-    send_all_blocks(app_id, &peer.address, blocks).await;
-}
-
-async fn send_all_blocks(app_id: String, address: &str, all_blocks: Vec<Block>) {
-    println!("Send all blocks to {}", address);
+    println!("Headers requested from {}", peer.address);
+    let locator: Vec<String> = msg
+        .payload
+        .as_ref()
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .unwrap_or_default();
+    let headers = chain.headers_after(&locator, MAX_HEADERS);
     let payload = P2PMessage {
-        event: P2PEvent::AllBlocksRecevied,
-        payload: Some(serde_json::to_string(&all_blocks).unwrap()),
+        event: P2PEvent::HeadersReceived,
+        payload: Some(serde_json::to_string(&headers).unwrap()),
         sender_id: app_id,
     };
-    send_message(address, payload).await;
+    send_message(&peer.address, payload).await;
 }
 
-async fn on_all_blocks_received(
-    _app_id: String,
+async fn on_headers_received(
+    app_id: String,
+    chain: &mut BlockChain,
+    peer: &Peer,
+    msg: &P2PMessage,
+    peers: Arc<FutureMutex<Peers>>,
+    _openport: u16,
+) {
+    println!("Got headers from {}", peer.address);
+    let headers: Vec<BlockHeader> = msg
+        .payload
+        .as_ref()
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .unwrap_or_default();
+    if let Err(err) = chain.add_headers(&headers) {
+        println!("Rejected headers from {}: {}", peer.address, err);
+        return;
+    }
+    // A full batch means the peer has more
+    if headers.len() == MAX_HEADERS {
+        let last_hash = headers.last().unwrap().hash.clone();
+        request_headers(app_id.clone(), &peer.address, vec![last_hash]).await;
+    }
+    request_blocks(app_id, chain, peers).await;
+}
+
+// Spreads the blocks of the best header chain over the peers
+async fn request_blocks(app_id: String, chain: &mut BlockChain, peers: Arc<FutureMutex<Peers>>) {
+    let now = Utc::now().timestamp();
+    let peers = peers.lock().await;
+    for address in peers.map.keys() {
+        let hashes = chain.blocks_to_download(address, now);
+        if hashes.is_empty() {
+            continue;
+        }
+        println!("Requesting {} blocks from {}", hashes.len(), address);
+        let payload = P2PMessage {
+            event: P2PEvent::BlocksRequested,
+            payload: Some(serde_json::to_string(&hashes).unwrap()),
+            sender_id: app_id.clone(),
+        };
+        send_message(address, payload).await;
+    }
+}
+
+async fn on_blocks_requested(
+    app_id: String,
     chain: &mut BlockChain,
     peer: &Peer,
     msg: &P2PMessage,
     _peers: Arc<FutureMutex<Peers>>,
     _openport: u16,
 ) {
-    println!("Got all blocks from {}", peer.address);
-    let blocks: Option<Vec<Block>> = msg
+    println!("Blocks requested from {}", peer.address);
+    let hashes: Vec<String> = msg
         .payload
         .as_ref()
-        .map(|payload| serde_json::from_str(payload).unwrap());
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .unwrap_or_default();
+    let blocks: Vec<Block> = hashes
+        .into_iter()
+        .filter_map(|hash| chain.get_block(hash))
+        .collect();
+    let payload = P2PMessage {
+        event: P2PEvent::BlocksReceived,
+        payload: Some(serde_json::to_string(&blocks).unwrap()),
+        sender_id: app_id,
+    };
+    send_message(&peer.address, payload).await;
+}
 
-    if let Some(blocks) = blocks {
-        if blocks.is_empty() {
-            return;
+async fn on_blocks_received(
+    app_id: String,
+    chain: &mut BlockChain,
+    peer: &Peer,
+    msg: &P2PMessage,
+    peers: Arc<FutureMutex<Peers>>,
+    _openport: u16,
+) {
+    println!("Got blocks from {}", peer.address);
+    let blocks: Vec<Block> = msg
+        .payload
+        .as_ref()
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .unwrap_or_default();
+    for block in blocks.into_iter() {
+        if let Err(err) = chain.add_block(block, &peer.address) {
+            println!("Rejected block from {}: {}", peer.address, err);
         }
-        chain.replace(blocks);
     }
+    request_blocks(app_id, chain, peers).await;
 }
 
 pub async fn broadcast_new_block(app_id: String, peers: Arc<FutureMutex<Peers>>, block: Block) {
     println!("Broadcast new block");
+    let now = Utc::now().timestamp();
+    peers.lock().await.seen.originate(&block.hash, now);
+    let item = InvItem::new(InvKind::Block, &block.hash, DEFAULT_TTL);
+    announce(app_id, peers, item).await;
+}
+
+// Tells the peers which don't have it yet about the block or transaction,
+// which they request if they still lack it
+async fn announce(app_id: String, peers: Arc<FutureMutex<Peers>>, item: InvItem) {
+    let mut peers = peers.lock().await;
+    let addresses: Vec<String> = peers
+        .map
+        .keys()
+        .filter(|address| !peers.knows(address, &item.hash))
+        .cloned()
+        .collect();
     let msg = P2PMessage {
-        event: P2PEvent::NewBlockNotified,
-        payload: Some(serde_json::to_string(&block).unwrap()),
-        sender_id: app_id.clone(),
+        event: P2PEvent::InventoryNotified,
+        payload: Some(serde_json::to_string(&vec![item.clone()]).unwrap()),
+        sender_id: app_id,
     };
-    broadcast_message(peers, msg).await;
+    for address in addresses.iter() {
+        peers.mark_known(address, &item.hash);
+        send_message(address, msg.clone()).await;
+    }
 }
 
-async fn on_new_block_notified(
-    _app_id: String,
+// Passes a validated block or transaction on to the peers which don't have it,
+// once and only while it has hops left
+async fn relay(app_id: String, peers: Arc<FutureMutex<Peers>>, kind: InvKind, hash: &str) {
+    let ttl = {
+        let mut peers = peers.lock().await;
+        let now = Utc::now().timestamp();
+        // Data nobody announced, like a block pushed to us, is relayed with the full TTL
+        let announced_ttl = peers
+            .in_flight
+            .remove(hash)
+            .map_or(DEFAULT_TTL, |item| item.ttl);
+        peers.seen.insert(hash, announced_ttl, now);
+        peers.seen.relay(hash, now)
+    };
+    if let Some(ttl) = ttl {
+        announce(app_id, peers, InvItem::new(kind, hash, ttl)).await;
+    }
+}
+
+async fn on_inventory_notified(
+    app_id: String,
+    chain: &mut BlockChain,
+    peer: &Peer,
+    msg: &P2PMessage,
+    peers: Arc<FutureMutex<Peers>>,
+    _openport: u16,
+) {
+    println!("Got inventory from {}", peer.address);
+    let items: Vec<InvItem> = msg
+        .payload
+        .as_ref()
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .unwrap_or_default();
+    let now = Utc::now().timestamp();
+    let mut peers = peers.lock().await;
+    let mut requests: HashMap<String, Vec<InvItem>> = HashMap::new();
+    // Data a peer didn't deliver in time is requested from the next peer which announced it
+    for (item, address) in peers.in_flight.expire(now) {
+        if lacks(chain, &item) {
+            requests.entry(address).or_default().push(item);
+        }
+    }
+    for item in items.into_iter() {
+        peers.mark_known(&peer.address, &item.hash);
+        // What's been validated or is being requested from another peer isn't requested again
+        if lacks(chain, &item)
+            && !peers.seen.contains(&item.hash)
+            && peers.in_flight.announce(&item, &peer.address, now)
+        {
+            requests.entry(peer.address.clone()).or_default().push(item);
+        }
+    }
+    for (address, items) in requests.into_iter() {
+        let payload = P2PMessage {
+            event: P2PEvent::DataRequested,
+            payload: Some(serde_json::to_string(&items).unwrap()),
+            sender_id: app_id.clone(),
+        };
+        send_message(&address, payload).await;
+    }
+}
+
+fn lacks(chain: &BlockChain, item: &InvItem) -> bool {
+    match item.kind {
+        InvKind::Block => !chain.has_block(&item.hash),
+        InvKind::Txn => chain.mempool_transaction(&item.hash).is_none(),
+    }
+}
+
+async fn on_data_requested(
+    app_id: String,
     chain: &mut BlockChain,
     peer: &Peer,
     msg: &P2PMessage,
     _peers: Arc<FutureMutex<Peers>>,
     _openport: u16,
+) {
+    println!("Data requested from {}", peer.address);
+    let items: Vec<InvItem> = msg
+        .payload
+        .as_ref()
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .unwrap_or_default();
+    for item in items.iter() {
+        let (event, payload) = match item.kind {
+            InvKind::Block => (
+                P2PEvent::NewBlockNotified,
+                chain
+                    .get_block(item.hash.clone())
+                    .map(|block| serde_json::to_string(&block).unwrap()),
+            ),
+            InvKind::Txn => (
+                P2PEvent::NewTxnNotified,
+                chain
+                    .mempool_transaction(&item.hash)
+                    .map(|txn| serde_json::to_string(&txn).unwrap()),
+            ),
+        };
+        if payload.is_some() {
+            let msg = P2PMessage {
+                event,
+                payload,
+                sender_id: app_id.clone(),
+            };
+            send_message(&peer.address, msg).await;
+        }
+    }
+}
+
+async fn on_new_block_notified(
+    app_id: String,
+    chain: &mut BlockChain,
+    peer: &Peer,
+    msg: &P2PMessage,
+    peers: Arc<FutureMutex<Peers>>,
+    _openport: u16,
 ) {
     println!("Got new block from {}", peer.address);
     let block: Option<Block> = msg
@@ -299,27 +642,75 @@ async fn on_new_block_notified(
         .map(|payload| serde_json::from_str(payload).unwrap());
 
     if let Some(block) = block {
-        chain.add_block(block);
+        let hash = block.hash.clone();
+        peers.lock().await.mark_known(&peer.address, &hash);
+        if let Err(err) = chain.add_block(block, &peer.address) {
+            println!("Rejected block from {}: {}", peer.address, err);
+            peers.lock().await.in_flight.remove(&hash);
+            return;
+        }
+        // Orphans are connected once the peer sends their ancestors, one by one
+        if let Some(missing_hash) = chain.missing_ancestor(&hash) {
+            request_block(app_id, &peer.address, missing_hash).await;
+            return;
+        }
+        // Side branches are only fully validated once the node switches to them
+        if !chain.is_active_block(&hash) {
+            peers.lock().await.in_flight.remove(&hash);
+            return;
+        }
+        relay(app_id, peers, InvKind::Block, &hash).await;
     }
 }
 
-pub async fn broadcast_new_txn(app_id: String, peers: Arc<FutureMutex<Peers>>, txn: Transaction) {
-    println!("Broadcast new block");
-    let msg = P2PMessage {
-        event: P2PEvent::NewTxnNotified,
-        payload: Some(serde_json::to_string(&txn).unwrap()),
-        sender_id: app_id.clone(),
+async fn request_block(app_id: String, address: &str, hash: String) {
+    println!("Requesting block {} from {}", hash, address);
+    let payload = P2PMessage {
+        event: P2PEvent::BlockRequested,
+        payload: Some(hash),
+        sender_id: app_id,
     };
-    broadcast_message(peers, msg).await;
+    send_message(address, payload).await;
 }
 
-async fn on_new_txn_notified(
-    _app_id: String,
+async fn on_block_requested(
+    app_id: String,
     chain: &mut BlockChain,
     peer: &Peer,
     msg: &P2PMessage,
     _peers: Arc<FutureMutex<Peers>>,
     _openport: u16,
+) {
+    println!("Block requested from {}", peer.address);
+    let block = msg
+        .payload
+        .as_ref()
+        .and_then(|hash| chain.get_block(hash.clone()));
+    if let Some(block) = block {
+        let payload = P2PMessage {
+            event: P2PEvent::NewBlockNotified,
+            payload: Some(serde_json::to_string(&block).unwrap()),
+            sender_id: app_id,
+        };
+        send_message(&peer.address, payload).await;
+    }
+}
+
+pub async fn broadcast_new_txn(app_id: String, peers: Arc<FutureMutex<Peers>>, txn: Transaction) {
+    println!("Broadcast new txn");
+    let now = Utc::now().timestamp();
+    peers.lock().await.seen.originate(&txn.hash, now);
+    let item = InvItem::new(InvKind::Txn, &txn.hash, DEFAULT_TTL);
+    announce(app_id, peers, item).await;
+}
+
+async fn on_new_txn_notified(
+    app_id: String,
+    chain: &mut BlockChain,
+    peer: &Peer,
+    msg: &P2PMessage,
+    peers: Arc<FutureMutex<Peers>>,
+    _openport: u16,
 ) {
     println!("Got new txn from {}", peer.address);
     let txn: Option<Transaction> = msg
@@ -327,6 +718,41 @@ async fn on_new_txn_notified(
         .as_ref()
         .map(|payload| serde_json::from_str(payload).unwrap());
     if let Some(txn) = txn {
-        chain.add_txn_to_mempool(txn);
+        let hash = txn.hash.clone();
+        peers.lock().await.mark_known(&peer.address, &hash);
+        if let Err(err) = chain.submit_transaction(txn) {
+            println!("Rejected txn from {}: {}", peer.address, err);
+            peers.lock().await.in_flight.remove(&hash);
+            return;
+        }
+        relay(app_id, peers, InvKind::Txn, &hash).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peers_remember_recent_inventory() {
+        // Given
+        let mut peers = Peers::new();
+        peers.add(&Peer::new("id", "peer:8000"));
+
+        // When
+        for idx in 0..=MAX_KNOWN_INVENTORY {
+            peers.mark_known("peer:8000", &idx.to_string());
+        }
+
+        // Then
+        assert!(!peers.knows("peer:8000", "0"));
+        assert!(peers.knows("peer:8000", "1"));
+        assert!(!peers.knows("other:8000", "1"));
+
+        // When
+        peers.remove("peer:8000");
+
+        // Then
+        assert!(!peers.knows("peer:8000", "1"));
     }
 }
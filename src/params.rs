@@ -0,0 +1,25 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainParams {
+    // Blocks the chain must have, by height
+    pub checkpoints: BTreeMap<u64, String>,
+    // Skips checking the transactions of the blocks the last checkpoint builds on
+    pub assume_valid: bool,
+}
+
+impl ChainParams {
+    pub fn last_checkpoint(&self) -> Option<(u64, &str)> {
+        self.checkpoints
+            .iter()
+            .next_back()
+            .map(|(height, hash)| (*height, hash.as_str()))
+    }
+
+    // Whether a block at `height` can be `hash`
+    pub fn matches_checkpoint(&self, height: u64, hash: &str) -> bool {
+        self.checkpoints
+            .get(&height)
+            .is_none_or(|checkpoint| checkpoint == hash)
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{error::Error, script::ScriptType, Transaction};
+
+const DEFAULT_DUST_THRESHOLD: u64 = 1;
+const DEFAULT_MAX_TXN_SIZE: usize = 100_000;
+const DEFAULT_MAX_TXN_INS: usize = 500;
+const DEFAULT_MAX_TXN_OUTS: usize = 500;
+// Data outputs a standard transaction can have
+const MAX_DATA_OUTPUTS: usize = 1;
+
+// Rules a transaction must follow to be relayed and put in the mempool.
+// Unlike consensus rules, nodes can pick their own, so blocks aren't checked against them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    // Outputs paying less than this are not worth spending
+    pub dust_threshold: u64,
+    // Bound of the size of a transaction in bytes
+    pub max_txn_size: usize,
+    pub max_txn_ins: usize,
+    pub max_txn_outs: usize,
+    // Output types which are not relayed
+    pub non_standard: Vec<ScriptType>,
+    // Coins per 1000 bytes a transaction must pay to be relayed
+    pub min_relay_fee_rate: u64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            dust_threshold: DEFAULT_DUST_THRESHOLD,
+            max_txn_size: DEFAULT_MAX_TXN_SIZE,
+            max_txn_ins: DEFAULT_MAX_TXN_INS,
+            max_txn_outs: DEFAULT_MAX_TXN_OUTS,
+            non_standard: vec![ScriptType::NonStandard],
+            min_relay_fee_rate: 0,
+        }
+    }
+}
+
+impl Policy {
+    pub fn check(&self, txn: &Transaction) -> Result<(), Error> {
+        if txn.size() > self.max_txn_size {
+            return Err(Error::new("Transaction is too large"));
+        }
+        if txn.txn_ins.len() > self.max_txn_ins {
+            return Err(Error::new("Transaction has too many inputs"));
+        }
+        if txn.txn_outs.len() > self.max_txn_outs {
+            return Err(Error::new("Transaction has too many outputs"));
+        }
+        let mut data_outputs = 0;
+        for txn_out in txn.txn_outs.iter() {
+            let script_type = txn_out.script_pubkey.script_type();
+            if self.non_standard.contains(&script_type) {
+                return Err(Error::new("Output script is non-standard"));
+            }
+            if script_type == ScriptType::Data {
+                data_outputs += 1;
+            } else if txn_out.amount < self.dust_threshold {
+                return Err(Error::new("Output is dust"));
+            }
+        }
+        if data_outputs > MAX_DATA_OUTPUTS {
+            return Err(Error::new("Transaction has too many data outputs"));
+        }
+        if txn.fee_rate() < self.min_relay_fee_rate {
+            return Err(Error::new("Fee rate is below the minimum relay fee"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        script::{Op, Script},
+        testutils,
+        transaction::{TxnIn, TxnOut},
+    };
+
+    fn txn_paying(txn_outs: Vec<TxnOut>, fee: u64) -> Transaction {
+        let amount = txn_outs.iter().map(|txn_out| txn_out.amount).sum::<u64>() + fee;
+        Transaction::new(vec![TxnIn::new("txn_hash", 0, amount)], txn_outs)
+    }
+
+    #[test]
+    fn standard_transaction_passes_policy() {
+        let txn = txn_paying(
+            vec![
+                TxnOut::new(&testutils::address("to"), 10),
+                TxnOut::data(b"document-hash"),
+            ],
+            0,
+        );
+        assert!(Policy::default().check(&txn).is_ok());
+    }
+
+    #[test]
+    fn dust_outputs_are_rejected() {
+        let txn = txn_paying(vec![TxnOut::new(&testutils::address("to"), 0)], 0);
+        let err = Policy::default().check(&txn).unwrap_err();
+        assert_eq!(err.msg, "Output is dust");
+    }
+
+    #[test]
+    fn non_standard_outputs_are_rejected() {
+        let script = Script::new(vec![Op::Push(vec![1])]);
+        let txn = txn_paying(vec![TxnOut::with_script(script, 10)], 0);
+        let err = Policy::default().check(&txn).unwrap_err();
+        assert_eq!(err.msg, "Output script is non-standard");
+
+        let policy = Policy {
+            non_standard: vec![ScriptType::NonStandard, ScriptType::Data],
+            ..Default::default()
+        };
+        let txn = txn_paying(vec![TxnOut::data(b"document-hash")], 1);
+        assert_eq!(
+            policy.check(&txn).unwrap_err().msg,
+            "Output script is non-standard"
+        );
+    }
+
+    #[test]
+    fn oversized_transactions_are_rejected() {
+        let txn_outs = vec![TxnOut::new(&testutils::address("to"), 10); 3];
+        let txn = txn_paying(txn_outs, 0);
+        let policy = Policy {
+            max_txn_outs: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check(&txn).unwrap_err().msg,
+            "Transaction has too many outputs"
+        );
+        let policy = Policy {
+            max_txn_size: txn.size() - 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check(&txn).unwrap_err().msg,
+            "Transaction is too large"
+        );
+    }
+
+    #[test]
+    fn transaction_must_pay_min_relay_fee() {
+        let policy = Policy {
+            min_relay_fee_rate: 20,
+            ..Default::default()
+        };
+        let txn = txn_paying(vec![TxnOut::new(&testutils::address("to"), 10)], 1);
+        assert!(txn.fee_rate() < 20);
+        let err = policy.check(&txn).unwrap_err();
+        assert_eq!(err.msg, "Fee rate is below the minimum relay fee");
+        let txn = txn_paying(vec![TxnOut::new(&testutils::address("to"), 10)], 10);
+        assert!(policy.check(&txn).is_ok());
+    }
+}
@@ -1,6 +1,9 @@
 use std::{io::Error, sync::Mutex};
 
-use crate::{Block, BlockChainSnapshot};
+use crate::{
+    fees::FeeEstimator, hashable::Hashable, mempool::MempoolEntry, script::Script,
+    transaction::UtxoEntry, Block, BlockChainSnapshot,
+};
 use pickledb::PickleDb;
 
 pub trait BaseRepository: Send + Sync {
@@ -8,16 +11,53 @@ pub trait BaseRepository: Send + Sync {
     fn get_block(&self, hash: String) -> Option<Block>;
     fn save_snapshot(&self, snapshot: &BlockChainSnapshot) -> Result<(), Error>;
     fn save_block(&self, block: &Block) -> Result<(), Error>;
+    // Removes blocks along with the UTXO set, undo data and tips built from them
     fn remove_all_blocks(&self) -> Result<(), Error>;
+    fn get_utxo(&self, txn_hash: &str, idx: i64) -> Option<UtxoEntry>;
+    fn save_utxo(&self, entry: &UtxoEntry) -> Result<(), Error>;
+    fn remove_utxo(&self, txn_hash: &str, idx: i64) -> Result<(), Error>;
+    // Unspent outputs locked by `script`, kept indexed as outputs are saved and removed
+    fn get_utxos_by_script(&self, script: &Script) -> Vec<UtxoEntry>;
+    // Hashes of the active chain blocks which confirmed transactions, by transaction hash
+    fn get_txn_block(&self, txn_hash: &str) -> Option<String>;
+    fn save_txn_block(&self, txn_hash: &str, block_hash: &str) -> Result<(), Error>;
+    fn remove_txn_block(&self, txn_hash: &str) -> Result<(), Error>;
+    // Hashes of the active chain transactions with a data output carrying `payload`,
+    // oldest first
+    fn get_data_txns(&self, payload: &[u8]) -> Vec<String>;
+    fn save_data_txn(&self, payload: &[u8], txn_hash: &str) -> Result<(), Error>;
+    fn remove_data_txn(&self, payload: &[u8], txn_hash: &str) -> Result<(), Error>;
+    // Hashes of the blocks of the active chain, by height
+    fn get_active_hash(&self, height: u64) -> Option<String>;
+    fn save_active_hash(&self, height: u64, hash: &str) -> Result<(), Error>;
+    fn remove_active_hash(&self, height: u64) -> Result<(), Error>;
+    // Outputs a block spent, to restore them when it's disconnected
+    fn get_undo(&self, block_hash: &str) -> Option<Vec<UtxoEntry>>;
+    fn save_undo(&self, block_hash: &str, spent: &[UtxoEntry]) -> Result<(), Error>;
+    fn load_mempool(&self) -> Vec<MempoolEntry>;
+    fn save_mempool(&self, entries: &[MempoolEntry]) -> Result<(), Error>;
+    // Writes out the mempool, which may only be saved at intervals otherwise
+    fn dump_mempool(&self) -> Result<(), Error>;
+    // Hashes of the blocks without known children, which end a branch
+    fn load_tips(&self) -> Vec<String>;
+    fn save_tips(&self, tips: &[String]) -> Result<(), Error>;
+    // Hashes of the blocks marked invalid, which makes their descendants invalid too
+    fn load_invalid_blocks(&self) -> Vec<String>;
+    fn save_invalid_blocks(&self, hashes: &[String]) -> Result<(), Error>;
+    fn load_fee_estimator(&self) -> Option<FeeEstimator>;
+    fn save_fee_estimator(&self, estimator: &FeeEstimator) -> Result<(), Error>;
 }
 
 pub struct PickleDBRepository {
     conn: Mutex<PickleDb>,
+    // The mempool changes with every transaction, so it's kept out of the chain's
+    // database, which would be rewritten as a whole each time
+    mempool_conn: Mutex<PickleDb>,
 }
 
 impl PickleDBRepository {
-    pub fn new(conn: Mutex<PickleDb>) -> Self {
-        Self { conn }
+    pub fn new(conn: Mutex<PickleDb>, mempool_conn: Mutex<PickleDb>) -> Self {
+        Self { conn, mempool_conn }
     }
 }
 
@@ -43,15 +83,183 @@ impl BaseRepository for PickleDBRepository {
     fn remove_all_blocks(&self) -> Result<(), Error> {
         let mut conn = self.conn.lock().unwrap();
         for key in conn.get_all().into_iter() {
-            let _ = conn.rem(key.as_str());
+            if key == "tips"
+                || [
+                    "block:", "utxo:", "undo:", "height:", "script:", "txn:", "data:",
+                ]
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+            {
+                let _ = conn.rem(key.as_str());
+            }
         }
         Ok(())
     }
+    fn get_utxo(&self, txn_hash: &str, idx: i64) -> Option<UtxoEntry> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<UtxoEntry>(format!("utxo:{}:{}", txn_hash, idx).as_str())
+    }
+    fn save_utxo(&self, entry: &UtxoEntry) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.set(
+            format!("utxo:{}:{}", entry.txn_hash, entry.idx).as_str(),
+            entry,
+        );
+        let key = script_key(&entry.txn_out.script_pubkey);
+        let mut outpoints = conn.get::<Vec<(String, i64)>>(&key).unwrap_or_default();
+        let outpoint = (entry.txn_hash.clone(), entry.idx);
+        if !outpoints.contains(&outpoint) {
+            outpoints.push(outpoint);
+            let _ = conn.set(&key, &outpoints);
+        }
+        Ok(())
+    }
+    fn remove_utxo(&self, txn_hash: &str, idx: i64) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let utxo_key = format!("utxo:{}:{}", txn_hash, idx);
+        let entry = match conn.get::<UtxoEntry>(&utxo_key) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        let _ = conn.rem(&utxo_key);
+        let key = script_key(&entry.txn_out.script_pubkey);
+        let mut outpoints = conn.get::<Vec<(String, i64)>>(&key).unwrap_or_default();
+        outpoints.retain(|(hash, outpoint_idx)| hash != txn_hash || *outpoint_idx != idx);
+        if outpoints.is_empty() {
+            let _ = conn.rem(&key);
+        } else {
+            let _ = conn.set(&key, &outpoints);
+        }
+        Ok(())
+    }
+    fn get_utxos_by_script(&self, script: &Script) -> Vec<UtxoEntry> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<Vec<(String, i64)>>(&script_key(script))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(txn_hash, idx)| {
+                conn.get::<UtxoEntry>(format!("utxo:{}:{}", txn_hash, idx).as_str())
+            })
+            .collect()
+    }
+    fn get_txn_block(&self, txn_hash: &str) -> Option<String> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<String>(format!("txn:{}", txn_hash).as_str())
+    }
+    fn save_txn_block(&self, txn_hash: &str, block_hash: &str) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.set(format!("txn:{}", txn_hash).as_str(), &block_hash);
+        Ok(())
+    }
+    fn remove_txn_block(&self, txn_hash: &str) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.rem(format!("txn:{}", txn_hash).as_str());
+        Ok(())
+    }
+    fn get_data_txns(&self, payload: &[u8]) -> Vec<String> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<Vec<String>>(&data_key(payload))
+            .unwrap_or_default()
+    }
+    fn save_data_txn(&self, payload: &[u8], txn_hash: &str) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let key = data_key(payload);
+        let mut txn_hashes = conn.get::<Vec<String>>(&key).unwrap_or_default();
+        if !txn_hashes.iter().any(|hash| hash == txn_hash) {
+            txn_hashes.push(txn_hash.to_string());
+            let _ = conn.set(&key, &txn_hashes);
+        }
+        Ok(())
+    }
+    fn remove_data_txn(&self, payload: &[u8], txn_hash: &str) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let key = data_key(payload);
+        let mut txn_hashes = conn.get::<Vec<String>>(&key).unwrap_or_default();
+        txn_hashes.retain(|hash| hash != txn_hash);
+        if txn_hashes.is_empty() {
+            let _ = conn.rem(&key);
+        } else {
+            let _ = conn.set(&key, &txn_hashes);
+        }
+        Ok(())
+    }
+    fn get_active_hash(&self, height: u64) -> Option<String> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<String>(format!("height:{}", height).as_str())
+    }
+    fn save_active_hash(&self, height: u64, hash: &str) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.set(format!("height:{}", height).as_str(), &hash);
+        Ok(())
+    }
+    fn remove_active_hash(&self, height: u64) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.rem(format!("height:{}", height).as_str());
+        Ok(())
+    }
+    fn get_undo(&self, block_hash: &str) -> Option<Vec<UtxoEntry>> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<Vec<UtxoEntry>>(format!("undo:{}", block_hash).as_str())
+    }
+    fn save_undo(&self, block_hash: &str, spent: &[UtxoEntry]) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.set(format!("undo:{}", block_hash).as_str(), &spent);
+        Ok(())
+    }
+    fn load_mempool(&self) -> Vec<MempoolEntry> {
+        let conn = self.mempool_conn.lock().unwrap();
+        conn.get::<Vec<MempoolEntry>>("mempool").unwrap_or_default()
+    }
+    fn save_mempool(&self, entries: &[MempoolEntry]) -> Result<(), Error> {
+        let mut conn = self.mempool_conn.lock().unwrap();
+        let _ = conn.set("mempool", &entries);
+        Ok(())
+    }
+    fn dump_mempool(&self) -> Result<(), Error> {
+        let mut conn = self.mempool_conn.lock().unwrap();
+        conn.dump().map_err(|err| Error::other(err.to_string()))
+    }
+    fn load_tips(&self) -> Vec<String> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<Vec<String>>("tips").unwrap_or_default()
+    }
+    fn save_tips(&self, tips: &[String]) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.set("tips", &tips);
+        Ok(())
+    }
+    fn load_invalid_blocks(&self) -> Vec<String> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<Vec<String>>("invalid_blocks")
+            .unwrap_or_default()
+    }
+    fn save_invalid_blocks(&self, hashes: &[String]) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.set("invalid_blocks", &hashes);
+        Ok(())
+    }
+    fn load_fee_estimator(&self) -> Option<FeeEstimator> {
+        let conn = self.conn.lock().unwrap();
+        conn.get::<FeeEstimator>("fee_estimator")
+    }
+    fn save_fee_estimator(&self, estimator: &FeeEstimator) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let _ = conn.set("fee_estimator", estimator);
+        Ok(())
+    }
+}
+
+fn script_key(script: &Script) -> String {
+    format!("script:{}", script.hash())
+}
+
+fn data_key(payload: &[u8]) -> String {
+    format!("data:{}", hex::encode(payload))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::testutils;
+    use crate::{testutils, Transaction};
 
     use super::*;
 
@@ -59,7 +267,7 @@ mod tests {
     fn test_pickle_repo_saving_snapshot_works_correctly() {
         // Given
         let conn = testutils::test_pickle_db();
-        let repo = PickleDBRepository::new(conn);
+        let repo = PickleDBRepository::new(conn, testutils::test_pickle_db());
         let mut snapshot = BlockChainSnapshot::new();
         snapshot.newest_hash = "newest_hash".to_string();
         snapshot.height = 2;
@@ -79,8 +287,8 @@ mod tests {
     fn test_pickle_repo_save_block_works_properly() {
         // Given
         let conn = testutils::test_pickle_db();
-        let repo = PickleDBRepository::new(conn);
-        let block = Block::mine("address", "prev_hash", 1, 1, &mut vec![]);
+        let repo = PickleDBRepository::new(conn, testutils::test_pickle_db());
+        let block = Block::mine("address", "prev_hash", 1, 1, 0, &mut vec![]);
 
         // When
         repo.save_block(&block).unwrap();
@@ -94,8 +302,8 @@ mod tests {
     fn test_pickle_repo_remove_all_blocks_works_properly() {
         // Given
         let conn = testutils::test_pickle_db();
-        let repo = PickleDBRepository::new(conn);
-        let block = Block::mine("address", "prev_hash", 1, 1, &mut vec![]);
+        let repo = PickleDBRepository::new(conn, testutils::test_pickle_db());
+        let block = Block::mine("address", "prev_hash", 1, 1, 0, &mut vec![]);
         repo.save_block(&block).unwrap();
 
         // When
@@ -105,4 +313,99 @@ mod tests {
         let actual = repo.get_block(block.hash.clone());
         assert!(actual.is_none());
     }
+
+    #[test]
+    fn test_pickle_repo_saving_utxos_and_undo_works_correctly() {
+        // Given
+        let conn = testutils::test_pickle_db();
+        let repo = PickleDBRepository::new(conn, testutils::test_pickle_db());
+        let txn = Transaction::from_coinbase(&testutils::address("miner"), 0);
+        let entry = UtxoEntry::new(&txn.hash, 0, txn.txn_outs[0].clone(), 1);
+
+        // When
+        repo.save_utxo(&entry).unwrap();
+        repo.save_undo("block_hash", std::slice::from_ref(&entry))
+            .unwrap();
+
+        // Then
+        assert_eq!(repo.get_utxo(&txn.hash, 0), Some(entry.clone()));
+        assert_eq!(repo.get_undo("block_hash"), Some(vec![entry]));
+
+        // When
+        repo.remove_utxo(&txn.hash, 0).unwrap();
+        repo.save_utxo(&UtxoEntry::new("other", 0, txn.txn_outs[0].clone(), 1))
+            .unwrap();
+        repo.remove_all_blocks().unwrap();
+
+        // Then
+        assert_eq!(repo.get_utxo(&txn.hash, 0), None);
+        assert_eq!(repo.get_utxo("other", 0), None);
+        assert_eq!(repo.get_undo("block_hash"), None);
+    }
+
+    #[test]
+    fn test_pickle_repo_saving_mempool_works_correctly() {
+        // Given
+        let conn = testutils::test_pickle_db();
+        let repo = PickleDBRepository::new(conn, testutils::test_pickle_db());
+        let txn = Transaction::from_coinbase(&testutils::address("miner"), 0);
+        let entries = vec![MempoolEntry {
+            txn,
+            time: 1,
+            height: 1,
+        }];
+
+        // When
+        repo.save_mempool(&entries).unwrap();
+        repo.dump_mempool().unwrap();
+
+        // Then
+        assert_eq!(repo.load_mempool(), entries);
+        assert!(!repo.conn.lock().unwrap().exists("mempool"));
+    }
+
+    #[test]
+    fn test_pickle_repo_indexes_utxos_by_script_and_txns_by_block() {
+        // Given
+        let conn = testutils::test_pickle_db();
+        let repo = PickleDBRepository::new(conn, testutils::test_pickle_db());
+        let txn = Transaction::from_coinbase(&testutils::address("miner"), 0);
+        let other = Transaction::from_coinbase(&testutils::address("other"), 0);
+        let entry = UtxoEntry::new(&txn.hash, 0, txn.txn_outs[0].clone(), 1);
+
+        // When
+        repo.save_utxo(&entry).unwrap();
+        repo.save_utxo(&UtxoEntry::new(
+            &other.hash,
+            0,
+            other.txn_outs[0].clone(),
+            1,
+        ))
+        .unwrap();
+        repo.save_txn_block(&txn.hash, "block_hash").unwrap();
+        repo.save_data_txn(b"payload", &txn.hash).unwrap();
+        repo.save_data_txn(b"payload", &other.hash).unwrap();
+
+        // Then
+        let script = &txn.txn_outs[0].script_pubkey;
+        assert_eq!(repo.get_utxos_by_script(script), vec![entry]);
+        assert_eq!(
+            repo.get_txn_block(&txn.hash),
+            Some("block_hash".to_string())
+        );
+        assert_eq!(
+            repo.get_data_txns(b"payload"),
+            vec![txn.hash.clone(), other.hash.clone()]
+        );
+
+        // When
+        repo.remove_utxo(&txn.hash, 0).unwrap();
+        repo.remove_txn_block(&txn.hash).unwrap();
+        repo.remove_data_txn(b"payload", &txn.hash).unwrap();
+
+        // Then
+        assert!(repo.get_utxos_by_script(script).is_empty());
+        assert_eq!(repo.get_txn_block(&txn.hash), None);
+        assert_eq!(repo.get_data_txns(b"payload"), vec![other.hash]);
+    }
 }
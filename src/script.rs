@@ -0,0 +1,770 @@
+use p256::ecdsa::{
+    signature::{Signature, Verifier},
+    VerifyingKey,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    address::Address,
+    error::Error,
+    hashable::Hashable,
+    transaction::{Transaction, LOCK_TIME_THRESHOLD},
+};
+
+const MAX_SCRIPT_SIZE: usize = 10_000;
+const MAX_ELEMENT_SIZE: usize = 1_280;
+const MAX_STACK_SIZE: usize = 1_000;
+// Pushes are free, every other op (and every key of CHECKMULTISIG) counts
+const MAX_OPS: usize = 201;
+// Bound of public keys a multisig script can check
+pub const MAX_MULTISIG_KEYS: usize = 16;
+// Longest push the two-byte length prefix can encode
+pub const MAX_PUSH_SIZE: usize = u16::MAX as usize;
+
+pub fn hash256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(&Sha256::digest(data)).to_vec()
+}
+
+fn verify_signature(public_key: &[u8], msg: &[u8], signature: &[u8]) -> bool {
+    let public_key = match VerifyingKey::from_sec1_bytes(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    let signature = match Signature::from_bytes(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    public_key.verify(msg, &signature).is_ok()
+}
+
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex_string = String::deserialize(deserializer)?;
+        hex::decode(hex_string).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+pub enum Op {
+    Push(#[serde(with = "hex_bytes")] Vec<u8>),
+    Dup,
+    Drop,
+    Hash256,
+    Sha256,
+    Equal,
+    EqualVerify,
+    Verify,
+    CheckSig,
+    CheckMultiSig,
+    CheckLockTimeVerify,
+    If,
+    Else,
+    EndIf,
+    Return,
+}
+
+impl Op {
+    fn code(&self) -> u8 {
+        match self {
+            Op::Push(_) => 0x01,
+            Op::Dup => 0x10,
+            Op::Drop => 0x11,
+            Op::Hash256 => 0x20,
+            Op::Sha256 => 0x21,
+            Op::Equal => 0x30,
+            Op::EqualVerify => 0x31,
+            Op::Verify => 0x32,
+            Op::CheckSig => 0x40,
+            Op::CheckMultiSig => 0x41,
+            Op::CheckLockTimeVerify => 0x50,
+            Op::If => 0x60,
+            Op::Else => 0x61,
+            Op::EndIf => 0x62,
+            Op::Return => 0x70,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Op> {
+        let op = match code {
+            0x10 => Op::Dup,
+            0x11 => Op::Drop,
+            0x20 => Op::Hash256,
+            0x21 => Op::Sha256,
+            0x30 => Op::Equal,
+            0x31 => Op::EqualVerify,
+            0x32 => Op::Verify,
+            0x40 => Op::CheckSig,
+            0x41 => Op::CheckMultiSig,
+            0x50 => Op::CheckLockTimeVerify,
+            0x60 => Op::If,
+            0x61 => Op::Else,
+            0x62 => Op::EndIf,
+            0x70 => Op::Return,
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+// Templates of locking scripts, which relay policy can tell apart
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone, Copy)]
+pub enum ScriptType {
+    PubKeyHash,
+    ScriptHash,
+    MultiSig,
+    Htlc,
+    Data,
+    NonStandard,
+}
+
+impl ScriptType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pubkeyhash" => Some(ScriptType::PubKeyHash),
+            "scripthash" => Some(ScriptType::ScriptHash),
+            "multisig" => Some(ScriptType::MultiSig),
+            "htlc" => Some(ScriptType::Htlc),
+            "data" => Some(ScriptType::Data),
+            "nonstandard" => Some(ScriptType::NonStandard),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone, Default)]
+pub struct Script(pub Vec<Op>);
+
+impl Script {
+    pub fn new(ops: Vec<Op>) -> Self {
+        Script(ops)
+    }
+
+    pub fn push_number(number: u64) -> Op {
+        let mut bytes = number.to_le_bytes().to_vec();
+        while bytes.last() == Some(&0) {
+            bytes.pop();
+        }
+        Op::Push(bytes)
+    }
+
+    // Pay-to-pubkey-hash, unlocked by a signature and the public key
+    pub fn p2pkh(public_key_hash: &[u8]) -> Self {
+        Script(vec![
+            Op::Dup,
+            Op::Hash256,
+            Op::Push(public_key_hash.to_vec()),
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    // Pay-to-script-hash, unlocked by the inputs of a redeem script and the script itself
+    pub fn p2sh(script_hash: &[u8]) -> Self {
+        Script(vec![Op::Hash256, Op::Push(script_hash.to_vec()), Op::Equal])
+    }
+
+    pub fn multisig(required: usize, public_keys: &[Vec<u8>]) -> Self {
+        let mut ops = vec![Script::push_number(required as u64)];
+        for public_key in public_keys.iter() {
+            ops.push(Op::Push(public_key.clone()));
+        }
+        ops.push(Script::push_number(public_keys.len() as u64));
+        ops.push(Op::CheckMultiSig);
+        Script(ops)
+    }
+
+    // Claimable by the recipient with the preimage of `hash`, or refundable after `timeout`.
+    // Unlocked by `<signature> <public key> <preimage> 1` or `<signature> <public key> 0`
+    pub fn htlc(
+        hash: &[u8],
+        recipient_key_hash: &[u8],
+        refund_key_hash: &[u8],
+        timeout: u64,
+    ) -> Self {
+        Script(vec![
+            Op::If,
+            Op::Sha256,
+            Op::Push(hash.to_vec()),
+            Op::EqualVerify,
+            Op::Dup,
+            Op::Hash256,
+            Op::Push(recipient_key_hash.to_vec()),
+            Op::Else,
+            Script::push_number(timeout),
+            Op::CheckLockTimeVerify,
+            Op::Drop,
+            Op::Dup,
+            Op::Hash256,
+            Op::Push(refund_key_hash.to_vec()),
+            Op::EndIf,
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    pub fn htlc_timeout(&self) -> Option<u64> {
+        match self.0.as_slice() {
+            [Op::If, Op::Sha256, Op::Push(_), Op::EqualVerify, Op::Dup, Op::Hash256, Op::Push(_), Op::Else, Op::Push(timeout), Op::CheckLockTimeVerify, Op::Drop, Op::Dup, Op::Hash256, Op::Push(_), Op::EndIf, Op::EqualVerify, Op::CheckSig] => {
+                to_number(timeout).ok()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn public_key_hash(&self) -> Option<&[u8]> {
+        match self.0.as_slice() {
+            [Op::Dup, Op::Hash256, Op::Push(public_key_hash), Op::EqualVerify, Op::CheckSig] => {
+                Some(public_key_hash)
+            }
+            _ => None,
+        }
+    }
+
+    // Malformed addresses get an unspendable script, which outputs are rejected for
+    pub fn for_address(address: &str) -> Self {
+        match Address::decode(address) {
+            Some((_, Address::PublicKeyHash(public_key_hash))) => Script::p2pkh(&public_key_hash),
+            Some((_, Address::ScriptHash(script_hash))) => Script::p2sh(&script_hash),
+            None => Script(vec![Op::Return]),
+        }
+    }
+
+    pub fn multisig_keys(&self) -> Option<(usize, Vec<&[u8]>)> {
+        match self.0.as_slice() {
+            [Op::Push(required), keys @ .., Op::Push(key_count), Op::CheckMultiSig] => {
+                let public_keys: Vec<&[u8]> = keys
+                    .iter()
+                    .map(|op| match op {
+                        Op::Push(key) => Some(key.as_slice()),
+                        _ => None,
+                    })
+                    .collect::<Option<_>>()?;
+                let required = to_number(required).ok()? as usize;
+                if to_number(key_count).ok()? as usize != public_keys.len() {
+                    return None;
+                }
+                Some((required, public_keys))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn script_hash(&self) -> Option<&[u8]> {
+        match self.0.as_slice() {
+            [Op::Hash256, Op::Push(script_hash), Op::Equal] => Some(script_hash),
+            _ => None,
+        }
+    }
+
+    // Provably unspendable output carrying arbitrary data
+    pub fn data(payload: &[u8]) -> Self {
+        Script(vec![Op::Return, Op::Push(payload.to_vec())])
+    }
+
+    pub fn data_payload(&self) -> Option<&[u8]> {
+        match self.0.as_slice() {
+            [Op::Return, Op::Push(payload)] => Some(payload),
+            _ => None,
+        }
+    }
+
+    pub fn is_unspendable(&self) -> bool {
+        matches!(self.0.first(), Some(Op::Return))
+    }
+
+    pub fn script_type(&self) -> ScriptType {
+        if self.public_key_hash().is_some() {
+            ScriptType::PubKeyHash
+        } else if self.script_hash().is_some() {
+            ScriptType::ScriptHash
+        } else if self.multisig_keys().is_some() {
+            ScriptType::MultiSig
+        } else if self.htlc_timeout().is_some() {
+            ScriptType::Htlc
+        } else if self.data_payload().is_some() {
+            ScriptType::Data
+        } else {
+            ScriptType::NonStandard
+        }
+    }
+
+    // Whether every push fits the length prefix, so the bytes encode the script unambiguously
+    pub fn is_well_formed(&self) -> bool {
+        self.0.iter().all(|op| match op {
+            Op::Push(data) => data.len() <= MAX_PUSH_SIZE,
+            _ => true,
+        })
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        self.0.iter().all(|op| matches!(op, Op::Push(_)))
+    }
+
+    pub fn pushed_data(&self) -> Vec<&[u8]> {
+        self.0
+            .iter()
+            .filter_map(|op| match op {
+                Op::Push(data) => Some(data.as_slice()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut ops = vec![];
+        let mut cursor = 0;
+        while cursor < bytes.len() {
+            let code = bytes[cursor];
+            cursor += 1;
+            if code == 0x01 {
+                let len_bytes = bytes.get(cursor..cursor + 2)?;
+                let len = u16::from_le_bytes([len_bytes[0], len_bytes[1]]) as usize;
+                cursor += 2;
+                ops.push(Op::Push(bytes.get(cursor..cursor + len)?.to_vec()));
+                cursor += len;
+            } else {
+                ops.push(Op::from_code(code)?);
+            }
+        }
+        Some(Script(ops))
+    }
+}
+
+impl Hashable for Script {
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for op in self.0.iter() {
+            bytes.push(op.code());
+            if let Op::Push(data) = op {
+                bytes.append(&mut (data.len() as u16).to_le_bytes().to_vec());
+                bytes.append(&mut data.clone());
+            }
+        }
+        bytes
+    }
+}
+
+fn to_number(item: &[u8]) -> Result<u64, Error> {
+    if item.len() > 8 {
+        return Err(Error::new("Script number overflow"));
+    }
+    let mut bytes = [0; 8];
+    bytes[..item.len()].copy_from_slice(item);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn is_true(item: &[u8]) -> bool {
+    item.iter().any(|byte| *byte != 0)
+}
+
+struct Interpreter<'a> {
+    txn: &'a Transaction,
+    stack: Vec<Vec<u8>>,
+    // Whether each enclosing IF/ELSE branch is being executed
+    branches: Vec<bool>,
+    op_count: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(txn: &'a Transaction, stack: Vec<Vec<u8>>) -> Self {
+        Self {
+            txn,
+            stack,
+            branches: vec![],
+            op_count: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, Error> {
+        self.stack
+            .pop()
+            .ok_or_else(|| Error::new("Script stack underflow"))
+    }
+
+    fn push(&mut self, item: Vec<u8>) -> Result<(), Error> {
+        if item.len() > MAX_ELEMENT_SIZE {
+            return Err(Error::new("Script element is too large"));
+        }
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return Err(Error::new("Script stack is too large"));
+        }
+        self.stack.push(item);
+        Ok(())
+    }
+
+    fn count_ops(&mut self, count: usize) -> Result<(), Error> {
+        self.op_count = self
+            .op_count
+            .checked_add(count)
+            .filter(|op_count| *op_count <= MAX_OPS)
+            .ok_or_else(|| Error::new("Script has too many ops"))?;
+        Ok(())
+    }
+
+    fn msg(&self) -> Vec<u8> {
+        self.txn.hash.clone().into_bytes()
+    }
+
+    fn execute(&mut self, script: &Script) -> Result<(), Error> {
+        if !script.is_well_formed() {
+            return Err(Error::new("Script push is too large"));
+        }
+        if script.bytes().len() > MAX_SCRIPT_SIZE {
+            return Err(Error::new("Script is too large"));
+        }
+        for op in script.0.iter() {
+            if !matches!(op, Op::Push(_)) {
+                self.count_ops(1)?;
+            }
+            let executing = self.branches.iter().all(|branch| *branch);
+            match op {
+                Op::If => {
+                    let branch = executing && is_true(&self.pop()?);
+                    self.branches.push(branch);
+                }
+                Op::Else => {
+                    let branch = self
+                        .branches
+                        .pop()
+                        .ok_or_else(|| Error::new("Unbalanced conditional"))?;
+                    self.branches.push(!branch);
+                }
+                Op::EndIf => {
+                    self.branches
+                        .pop()
+                        .ok_or_else(|| Error::new("Unbalanced conditional"))?;
+                }
+                _ if executing => self.step(op)?,
+                _ => {}
+            }
+        }
+        if !self.branches.is_empty() {
+            return Err(Error::new("Unbalanced conditional"));
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, op: &Op) -> Result<(), Error> {
+        match op {
+            Op::Push(data) => self.push(data.clone())?,
+            Op::Dup => {
+                let item = self.pop()?;
+                self.push(item.clone())?;
+                self.push(item)?;
+            }
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Hash256 => {
+                let item = self.pop()?;
+                self.push(hash256(&item))?;
+            }
+            Op::Sha256 => {
+                let item = self.pop()?;
+                self.push(Sha256::digest(&item).to_vec())?;
+            }
+            Op::Equal => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(vec![(a == b) as u8])?;
+            }
+            Op::EqualVerify => {
+                if self.pop()? != self.pop()? {
+                    return Err(Error::new("Script failed at EQUALVERIFY"));
+                }
+            }
+            Op::Verify => {
+                if !is_true(&self.pop()?) {
+                    return Err(Error::new("Script failed at VERIFY"));
+                }
+            }
+            Op::CheckSig => {
+                let public_key = self.pop()?;
+                let signature = self.pop()?;
+                let verified = verify_signature(&public_key, &self.msg(), &signature);
+                self.push(vec![verified as u8])?;
+            }
+            Op::CheckMultiSig => {
+                // Counts come off the stack, so they're bounded before counting or popping
+                let key_count = to_number(&self.pop()?)?;
+                if key_count > MAX_MULTISIG_KEYS as u64 || key_count > self.stack.len() as u64 {
+                    return Err(Error::new("Invalid multisig key count"));
+                }
+                let key_count = key_count as usize;
+                self.count_ops(key_count)?;
+                let mut public_keys = vec![];
+                for _ in 0..key_count {
+                    public_keys.push(self.pop()?);
+                }
+                let required = to_number(&self.pop()?)?;
+                if required > key_count as u64 {
+                    return Err(Error::new("Invalid multisig threshold"));
+                }
+                let required = required as usize;
+                let mut signatures = vec![];
+                for _ in 0..required {
+                    signatures.push(self.pop()?);
+                }
+                let verified = required > 0
+                    && required <= key_count
+                    && self.verify_multisig(&public_keys, &signatures);
+                self.push(vec![verified as u8])?;
+            }
+            Op::CheckLockTimeVerify => {
+                let lock_time = to_number(
+                    self.stack
+                        .last()
+                        .ok_or_else(|| Error::new("Script stack underflow"))?,
+                )?;
+                let txn_lock_time = self.txn.lock_time;
+                let same_kind =
+                    (lock_time < LOCK_TIME_THRESHOLD) == (txn_lock_time < LOCK_TIME_THRESHOLD);
+                if txn_lock_time == 0 || !same_kind || txn_lock_time < lock_time {
+                    return Err(Error::new("Script failed at CHECKLOCKTIMEVERIFY"));
+                }
+            }
+            Op::Return => return Err(Error::new("Script failed at RETURN")),
+            Op::If | Op::Else | Op::EndIf => {}
+        }
+        Ok(())
+    }
+
+    // Every signature must come from a distinct key, in any order
+    fn verify_multisig(&self, public_keys: &[Vec<u8>], signatures: &[Vec<u8>]) -> bool {
+        let msg = self.msg();
+        let mut used = vec![false; public_keys.len()];
+        for signature in signatures.iter() {
+            let signed_key = (0..public_keys.len())
+                .find(|idx| !used[*idx] && verify_signature(&public_keys[*idx], &msg, signature));
+            match signed_key {
+                Some(idx) => used[idx] = true,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn succeeded(&self) -> bool {
+        self.stack.last().is_some_and(|item| is_true(item))
+    }
+}
+
+// Runs the unlocking script of an input against the locking script of the output it spends
+pub fn verify_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    txn: &Transaction,
+) -> Result<(), Error> {
+    if !script_sig.is_push_only() {
+        return Err(Error::new("Unlocking script must only push data"));
+    }
+    let mut interpreter = Interpreter::new(txn, vec![]);
+    interpreter.execute(script_sig)?;
+    let unlocked_stack = interpreter.stack.clone();
+    interpreter.execute(script_pubkey)?;
+    if !interpreter.succeeded() {
+        return Err(Error::new("Script failed"));
+    }
+
+    // The last item pushed to a pay-to-script-hash output is the redeem script itself
+    if script_pubkey.script_hash().is_some() {
+        let mut stack = unlocked_stack;
+        let redeem_script = stack
+            .pop()
+            .and_then(|bytes| Script::from_bytes(&bytes))
+            .ok_or_else(|| Error::new("Invalid redeem script"))?;
+        let mut interpreter = Interpreter::new(txn, stack);
+        interpreter.execute(&redeem_script)?;
+        if !interpreter.succeeded() {
+            return Err(Error::new("Redeem script failed"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testutils, transaction::TxnOut};
+
+    fn test_txn() -> Transaction {
+        Transaction::new(vec![], vec![TxnOut::new(&testutils::address("to"), 10)])
+    }
+
+    fn sign(txn: &Transaction, wallet: &crate::Wallet) -> Vec<u8> {
+        hex::decode(wallet.sign(&hex::encode(&txn.hash))).unwrap()
+    }
+
+    #[test]
+    fn script_bytes_round_trip() {
+        let script = Script::multisig(2, &[vec![1; 65], vec![2; 65]]);
+        assert_eq!(Script::from_bytes(&script.bytes()), Some(script));
+    }
+
+    #[test]
+    fn p2pkh_is_unlocked_by_signature_of_key() {
+        // Given
+        let wallet = testutils::test_wallet();
+        let txn = test_txn();
+        let public_key = wallet.public_key();
+        let script_sig = Script::new(vec![Op::Push(sign(&txn, &wallet)), Op::Push(public_key)]);
+
+        // When
+        let result = verify_script(&script_sig, &Script::for_address(&wallet.address), &txn);
+
+        // Then
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn p2pkh_is_not_unlocked_by_other_key() {
+        // Given
+        let (wallet, other) = (testutils::test_wallet(), testutils::test_wallet());
+        let txn = test_txn();
+        let script_sig = Script::new(vec![
+            Op::Push(sign(&txn, &other)),
+            Op::Push(other.public_key()),
+        ]);
+
+        // When
+        let err = verify_script(&script_sig, &Script::for_address(&wallet.address), &txn);
+
+        // Then
+        assert_eq!(err.unwrap_err().msg, "Script failed at EQUALVERIFY");
+    }
+
+    #[test]
+    fn hash_lock_is_unlocked_by_preimage() {
+        let preimage = b"secret".to_vec();
+        let script_pubkey = Script::new(vec![
+            Op::Sha256,
+            Op::Push(Sha256::digest(&preimage).to_vec()),
+            Op::Equal,
+        ]);
+        let txn = test_txn();
+
+        let unlocked = Script::new(vec![Op::Push(preimage)]);
+        assert!(verify_script(&unlocked, &script_pubkey, &txn).is_ok());
+
+        let wrong = Script::new(vec![Op::Push(b"guess".to_vec())]);
+        assert!(verify_script(&wrong, &script_pubkey, &txn).is_err());
+    }
+
+    #[test]
+    fn conditionals_execute_one_branch() {
+        let script_pubkey = Script::new(vec![
+            Op::If,
+            Script::push_number(2),
+            Op::Else,
+            Script::push_number(3),
+            Op::EndIf,
+            Script::push_number(3),
+            Op::Equal,
+        ]);
+        let txn = test_txn();
+
+        let take_else = Script::new(vec![Op::Push(vec![])]);
+        assert!(verify_script(&take_else, &script_pubkey, &txn).is_ok());
+
+        let take_if = Script::new(vec![Op::Push(vec![1])]);
+        assert!(verify_script(&take_if, &script_pubkey, &txn).is_err());
+    }
+
+    #[test]
+    fn data_output_is_unspendable() {
+        let script_pubkey = Script::data(b"document-hash");
+        let script_sig = Script::new(vec![Op::Push(vec![1])]);
+        let err = verify_script(&script_sig, &script_pubkey, &test_txn()).unwrap_err();
+        assert_eq!(err.msg, "Script failed at RETURN");
+        assert_eq!(
+            script_pubkey.data_payload(),
+            Some(b"document-hash".as_slice())
+        );
+    }
+
+    #[test]
+    fn conditionals_must_be_balanced() {
+        let script_pubkey = Script::new(vec![Script::push_number(1), Op::If]);
+        let err = verify_script(&Script::default(), &script_pubkey, &test_txn()).unwrap_err();
+        assert_eq!(err.msg, "Unbalanced conditional");
+    }
+
+    #[test]
+    fn check_lock_time_verify_compares_transaction_lock_time() {
+        // Given
+        let script_pubkey = Script::new(vec![Script::push_number(10), Op::CheckLockTimeVerify]);
+        let mut txn = test_txn();
+
+        // Then
+        txn.set_lock_time(9);
+        let err = verify_script(&Script::default(), &script_pubkey, &txn).unwrap_err();
+        assert_eq!(err.msg, "Script failed at CHECKLOCKTIMEVERIFY");
+
+        txn.set_lock_time(LOCK_TIME_THRESHOLD + 10);
+        assert!(verify_script(&Script::default(), &script_pubkey, &txn).is_err());
+
+        txn.set_lock_time(10);
+        assert!(verify_script(&Script::default(), &script_pubkey, &txn).is_ok());
+    }
+
+    #[test]
+    fn unlocking_script_must_only_push_data() {
+        let script_sig = Script::new(vec![Script::push_number(1), Op::Dup]);
+        let err = verify_script(&script_sig, &Script::default(), &test_txn()).unwrap_err();
+        assert_eq!(err.msg, "Unlocking script must only push data");
+    }
+
+    #[test]
+    fn script_is_bounded_by_op_count() {
+        let mut ops = vec![Script::push_number(1)];
+        ops.append(&mut vec![Op::Dup; MAX_OPS + 1]);
+        let script_pubkey = Script::new(ops);
+        let err = verify_script(&Script::default(), &script_pubkey, &test_txn()).unwrap_err();
+        assert_eq!(err.msg, "Script has too many ops");
+    }
+
+    #[test]
+    fn multisig_key_count_is_bounded() {
+        // Given
+        let mut ops = vec![Script::push_number(1)];
+        ops.append(&mut vec![Op::Push(vec![2]); MAX_MULTISIG_KEYS + 1]);
+        ops.push(Script::push_number(MAX_MULTISIG_KEYS as u64 + 1));
+        ops.push(Op::CheckMultiSig);
+        let too_many_keys = Script::new(ops);
+        let huge_count = Script::new(vec![
+            Script::push_number(1),
+            Script::push_number(u64::MAX),
+            Op::CheckMultiSig,
+        ]);
+
+        // When
+        let too_many_err =
+            verify_script(&Script::default(), &too_many_keys, &test_txn()).unwrap_err();
+        let huge_err = verify_script(&Script::default(), &huge_count, &test_txn()).unwrap_err();
+
+        // Then
+        assert_eq!(too_many_err.msg, "Invalid multisig key count");
+        assert_eq!(huge_err.msg, "Invalid multisig key count");
+    }
+
+    #[test]
+    fn pushes_must_fit_length_prefix() {
+        let script_sig = Script::new(vec![Op::Push(vec![1; MAX_PUSH_SIZE + 1])]);
+        assert!(!script_sig.is_well_formed());
+        let err = verify_script(&script_sig, &Script::default(), &test_txn()).unwrap_err();
+        assert_eq!(err.msg, "Script push is too large");
+    }
+
+    #[test]
+    fn script_is_bounded_by_element_size() {
+        let script_sig = Script::new(vec![Op::Push(vec![1; MAX_ELEMENT_SIZE + 1])]);
+        let err = verify_script(&script_sig, &Script::default(), &test_txn()).unwrap_err();
+        assert_eq!(err.msg, "Script element is too large");
+    }
+}
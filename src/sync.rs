@@ -0,0 +1,211 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::block::BlockHeader;
+
+// Bound of headers sent at once
+pub const MAX_HEADERS: usize = 2000;
+// Bound of headers kept ahead of their blocks
+const MAX_PENDING_HEADERS: usize = 10 * MAX_HEADERS;
+// Bound of blocks being downloaded from a peer at once
+const MAX_BLOCKS_IN_FLIGHT: usize = 16;
+// Blocks past the chain tip which are downloaded at once, which bounds the orphans kept
+const DOWNLOAD_WINDOW: usize = 64;
+// Seconds a peer has to deliver requested blocks before they're requested again
+const DOWNLOAD_TIMEOUT: i64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncStatus {
+    pub header_height: u64,
+    pub block_height: u64,
+    pub best_header_hash: String,
+    pub blocks_in_flight: usize,
+    pub is_synced: bool,
+}
+
+// Headers validated ahead of their blocks, and the blocks being downloaded
+#[derive(Debug, Default)]
+pub struct HeaderSync {
+    // Headers with the work of the chain up to them, by hash
+    headers: HashMap<String, (BlockHeader, u128)>,
+    // Peers the blocks were requested from and when, by hash
+    downloads: HashMap<String, (String, i64)>,
+}
+
+impl HeaderSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&BlockHeader> {
+        self.headers.get(hash).map(|(header, _)| header)
+    }
+
+    pub fn chain_work(&self, hash: &str) -> Option<u128> {
+        self.headers.get(hash).map(|(_, chain_work)| *chain_work)
+    }
+
+    // Keeps the header, making room when full by dropping the tip with the least work,
+    // which leaves every other header connected. Returns whether the header was kept,
+    // which it isn't when it has no more work than the tips it could replace
+    pub fn insert(&mut self, header: BlockHeader, chain_work: u128) -> bool {
+        if self.headers.len() >= MAX_PENDING_HEADERS {
+            let weakest = self
+                .tips()
+                .into_iter()
+                .filter(|tip| tip.hash != header.prev_hash)
+                .map(|tip| (tip.hash.clone(), self.chain_work(&tip.hash).unwrap_or(0)))
+                .min_by_key(|(_, work)| *work);
+            match weakest {
+                Some((hash, work)) if work < chain_work => self.remove(&hash),
+                _ => return false,
+            }
+        }
+        self.headers
+            .insert(header.hash.clone(), (header, chain_work));
+        true
+    }
+
+    // Forgets the header once its block is stored
+    pub fn remove(&mut self, hash: &str) {
+        self.headers.remove(hash);
+        self.downloads.remove(hash);
+    }
+
+    // Headers with the most work first
+    pub fn by_work(&self) -> Vec<&BlockHeader> {
+        let mut headers: Vec<&(BlockHeader, u128)> = self.headers.values().collect();
+        headers.sort_by_key(|(_, chain_work)| std::cmp::Reverse(*chain_work));
+        headers.into_iter().map(|(header, _)| header).collect()
+    }
+
+    // Headers no other header builds on
+    pub fn tips(&self) -> Vec<&BlockHeader> {
+        let parents: HashSet<&String> = self
+            .headers
+            .values()
+            .map(|(header, _)| &header.prev_hash)
+            .collect();
+        self.headers
+            .values()
+            .map(|(header, _)| header)
+            .filter(|header| !parents.contains(&header.hash))
+            .collect()
+    }
+
+    pub fn in_flight(&self, now: i64) -> usize {
+        self.downloads
+            .values()
+            .filter(|(_, time)| time + DOWNLOAD_TIMEOUT > now)
+            .count()
+    }
+
+    // Assigns the peer as many of the `missing` blocks, oldest first, as it has room for,
+    // leaving out the ones another peer is still expected to deliver
+    pub fn assign(&mut self, missing: &[String], peer: &str, now: i64) -> Vec<String> {
+        let in_flight = self
+            .downloads
+            .values()
+            .filter(|(address, time)| address == peer && time + DOWNLOAD_TIMEOUT > now)
+            .count();
+        let hashes: Vec<String> = missing
+            .iter()
+            .take(DOWNLOAD_WINDOW)
+            .filter(|hash| {
+                self.downloads
+                    .get(*hash)
+                    .is_none_or(|(_, time)| time + DOWNLOAD_TIMEOUT <= now)
+            })
+            .take(MAX_BLOCKS_IN_FLIGHT.saturating_sub(in_flight))
+            .cloned()
+            .collect();
+        for hash in hashes.iter() {
+            self.downloads.insert(hash.clone(), (peer.to_string(), now));
+        }
+        hashes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(hash: &str, prev_hash: &str) -> BlockHeader {
+        BlockHeader {
+            hash: hash.to_string(),
+            prev_hash: prev_hash.to_string(),
+            height: 1,
+            difficulty: 1,
+            nonce: 0,
+            timestamp: 0,
+            txns_hash: String::new(),
+        }
+    }
+
+    #[test]
+    fn tips_are_headers_without_children() {
+        // Given
+        let mut sync = HeaderSync::new();
+        sync.insert(header("a", ""), 16);
+        sync.insert(header("b", "a"), 32);
+        sync.insert(header("c", "a"), 48);
+
+        // Then
+        let mut tips: Vec<&str> = sync.tips().iter().map(|tip| tip.hash.as_str()).collect();
+        tips.sort_unstable();
+        assert_eq!(tips, vec!["b", "c"]);
+        assert_eq!(sync.by_work()[0].hash, "c");
+    }
+
+    #[test]
+    fn blocks_are_assigned_to_one_peer_until_timeout() {
+        // Given
+        let mut sync = HeaderSync::new();
+        let missing: Vec<String> = (0..40).map(|idx| idx.to_string()).collect();
+
+        // When
+        let first = sync.assign(&missing, "a", 0);
+        let second = sync.assign(&missing, "b", 0);
+        let again = sync.assign(&missing, "a", 0);
+
+        // Then
+        assert_eq!(first, missing[..MAX_BLOCKS_IN_FLIGHT].to_vec());
+        assert_eq!(second, missing[MAX_BLOCKS_IN_FLIGHT..32].to_vec());
+        assert!(again.is_empty());
+        assert_eq!(sync.in_flight(0), 32);
+
+        // When
+        let retried = sync.assign(&missing, "c", DOWNLOAD_TIMEOUT);
+
+        // Then
+        assert_eq!(retried, missing[..MAX_BLOCKS_IN_FLIGHT].to_vec());
+    }
+
+    #[test]
+    fn weakest_tip_makes_room_once_full() {
+        // Given
+        let mut sync = HeaderSync::new();
+        sync.insert(header("weak", ""), 1);
+        for idx in 1..MAX_PENDING_HEADERS {
+            let prev_hash = if idx == 1 {
+                String::new()
+            } else {
+                (idx - 1).to_string()
+            };
+            sync.insert(header(&idx.to_string(), &prev_hash), 16 * idx as u128);
+        }
+
+        // When
+        let last = (MAX_PENDING_HEADERS - 1).to_string();
+        let kept = sync.insert(header("next", &last), u128::MAX);
+        let weaker = sync.insert(header("weaker", "1"), 0);
+        let beyond = sync.insert(header("beyond", "next"), u128::MAX);
+
+        // Then
+        assert!(kept);
+        assert!(sync.get("weak").is_none());
+        assert!(sync.get("next").is_some());
+        assert!(!weaker);
+        assert!(!beyond);
+    }
+}
@@ -2,8 +2,13 @@ use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use std::{collections::HashMap, io::Error, iter, sync::Mutex};
 
+use crate::address::Network;
+use crate::script::{hash256, Script};
 use crate::Wallet;
-use crate::{repo::BaseRepository, Block, BlockChainSnapshot};
+use crate::{
+    fees::FeeEstimator, mempool::MempoolEntry, repo::BaseRepository, transaction::UtxoEntry, Block,
+    BlockChainSnapshot,
+};
 
 pub fn random_string(len: usize) -> String {
     let mut rng = thread_rng();
@@ -16,6 +21,15 @@ pub fn random_string(len: usize) -> String {
 pub struct TestRepository {
     snapshot: Mutex<Option<BlockChainSnapshot>>,
     blocks: Mutex<HashMap<String, Block>>,
+    utxos: Mutex<HashMap<(String, i64), UtxoEntry>>,
+    undo: Mutex<HashMap<String, Vec<UtxoEntry>>>,
+    active_hashes: Mutex<HashMap<u64, String>>,
+    txn_blocks: Mutex<HashMap<String, String>>,
+    data_txns: Mutex<HashMap<Vec<u8>, Vec<String>>>,
+    mempool: Mutex<Vec<MempoolEntry>>,
+    tips: Mutex<Vec<String>>,
+    invalid_blocks: Mutex<Vec<String>>,
+    fee_estimator: Mutex<Option<FeeEstimator>>,
 }
 
 impl TestRepository {
@@ -23,6 +37,15 @@ impl TestRepository {
         Self {
             snapshot: Mutex::new(None),
             blocks: Mutex::new(HashMap::new()),
+            utxos: Mutex::new(HashMap::new()),
+            undo: Mutex::new(HashMap::new()),
+            active_hashes: Mutex::new(HashMap::new()),
+            txn_blocks: Mutex::new(HashMap::new()),
+            data_txns: Mutex::new(HashMap::new()),
+            mempool: Mutex::new(vec![]),
+            tips: Mutex::new(vec![]),
+            invalid_blocks: Mutex::new(vec![]),
+            fee_estimator: Mutex::new(None),
         }
     }
 }
@@ -47,22 +70,140 @@ impl BaseRepository for TestRepository {
     }
     fn remove_all_blocks(&self) -> Result<(), Error> {
         self.blocks.lock().unwrap().clear();
+        self.utxos.lock().unwrap().clear();
+        self.undo.lock().unwrap().clear();
+        self.active_hashes.lock().unwrap().clear();
+        self.txn_blocks.lock().unwrap().clear();
+        self.data_txns.lock().unwrap().clear();
+        self.tips.lock().unwrap().clear();
+        Ok(())
+    }
+    fn get_utxo(&self, txn_hash: &str, idx: i64) -> Option<UtxoEntry> {
+        let key = (txn_hash.to_string(), idx);
+        self.utxos.lock().unwrap().get(&key).cloned()
+    }
+    fn save_utxo(&self, entry: &UtxoEntry) -> Result<(), Error> {
+        let key = (entry.txn_hash.clone(), entry.idx);
+        self.utxos.lock().unwrap().insert(key, entry.clone());
+        Ok(())
+    }
+    fn remove_utxo(&self, txn_hash: &str, idx: i64) -> Result<(), Error> {
+        let key = (txn_hash.to_string(), idx);
+        self.utxos.lock().unwrap().remove(&key);
+        Ok(())
+    }
+    fn get_utxos_by_script(&self, script: &Script) -> Vec<UtxoEntry> {
+        let utxos = self.utxos.lock().unwrap();
+        let mut entries: Vec<UtxoEntry> = utxos
+            .values()
+            .filter(|entry| &entry.txn_out.script_pubkey == script)
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| (a.height, &a.txn_hash, a.idx).cmp(&(b.height, &b.txn_hash, b.idx)));
+        entries
+    }
+    fn get_txn_block(&self, txn_hash: &str) -> Option<String> {
+        self.txn_blocks.lock().unwrap().get(txn_hash).cloned()
+    }
+    fn save_txn_block(&self, txn_hash: &str, block_hash: &str) -> Result<(), Error> {
+        let (txn_hash, block_hash) = (txn_hash.to_string(), block_hash.to_string());
+        self.txn_blocks.lock().unwrap().insert(txn_hash, block_hash);
+        Ok(())
+    }
+    fn remove_txn_block(&self, txn_hash: &str) -> Result<(), Error> {
+        self.txn_blocks.lock().unwrap().remove(txn_hash);
+        Ok(())
+    }
+    fn get_data_txns(&self, payload: &[u8]) -> Vec<String> {
+        let data_txns = self.data_txns.lock().unwrap();
+        data_txns.get(payload).cloned().unwrap_or_default()
+    }
+    fn save_data_txn(&self, payload: &[u8], txn_hash: &str) -> Result<(), Error> {
+        let mut data_txns = self.data_txns.lock().unwrap();
+        let txn_hashes = data_txns.entry(payload.to_vec()).or_default();
+        if !txn_hashes.iter().any(|hash| hash == txn_hash) {
+            txn_hashes.push(txn_hash.to_string());
+        }
+        Ok(())
+    }
+    fn remove_data_txn(&self, payload: &[u8], txn_hash: &str) -> Result<(), Error> {
+        let mut data_txns = self.data_txns.lock().unwrap();
+        if let Some(txn_hashes) = data_txns.get_mut(payload) {
+            txn_hashes.retain(|hash| hash != txn_hash);
+            if txn_hashes.is_empty() {
+                data_txns.remove(payload);
+            }
+        }
+        Ok(())
+    }
+    fn get_active_hash(&self, height: u64) -> Option<String> {
+        self.active_hashes.lock().unwrap().get(&height).cloned()
+    }
+    fn save_active_hash(&self, height: u64, hash: &str) -> Result<(), Error> {
+        let hash = hash.to_string();
+        self.active_hashes.lock().unwrap().insert(height, hash);
+        Ok(())
+    }
+    fn remove_active_hash(&self, height: u64) -> Result<(), Error> {
+        self.active_hashes.lock().unwrap().remove(&height);
+        Ok(())
+    }
+    fn get_undo(&self, block_hash: &str) -> Option<Vec<UtxoEntry>> {
+        self.undo.lock().unwrap().get(block_hash).cloned()
+    }
+    fn save_undo(&self, block_hash: &str, spent: &[UtxoEntry]) -> Result<(), Error> {
+        let block_hash = block_hash.to_string();
+        self.undo.lock().unwrap().insert(block_hash, spent.to_vec());
+        Ok(())
+    }
+    fn load_mempool(&self) -> Vec<MempoolEntry> {
+        self.mempool.lock().unwrap().clone()
+    }
+    fn save_mempool(&self, entries: &[MempoolEntry]) -> Result<(), Error> {
+        *self.mempool.lock().unwrap() = entries.to_vec();
+        Ok(())
+    }
+    fn dump_mempool(&self) -> Result<(), Error> {
+        Ok(())
+    }
+    fn load_tips(&self) -> Vec<String> {
+        self.tips.lock().unwrap().clone()
+    }
+    fn save_tips(&self, tips: &[String]) -> Result<(), Error> {
+        *self.tips.lock().unwrap() = tips.to_vec();
+        Ok(())
+    }
+    fn load_invalid_blocks(&self) -> Vec<String> {
+        self.invalid_blocks.lock().unwrap().clone()
+    }
+    fn save_invalid_blocks(&self, hashes: &[String]) -> Result<(), Error> {
+        *self.invalid_blocks.lock().unwrap() = hashes.to_vec();
+        Ok(())
+    }
+    fn load_fee_estimator(&self) -> Option<FeeEstimator> {
+        self.fee_estimator.lock().unwrap().clone()
+    }
+    fn save_fee_estimator(&self, estimator: &FeeEstimator) -> Result<(), Error> {
+        *self.fee_estimator.lock().unwrap() = Some(estimator.clone());
         Ok(())
     }
 }
 
 pub fn test_pickle_db() -> Mutex<PickleDb> {
     let temp_path = std::env::temp_dir().join(format!("{}.db", random_string(32)));
-    let db = Mutex::new(PickleDb::new(
+    Mutex::new(PickleDb::new(
         temp_path,
         PickleDbDumpPolicy::AutoDump,
         SerializationMethod::Bin,
-    ));
-    db
+    ))
 }
 
 pub fn test_wallet() -> Wallet {
     let temp_path = std::env::temp_dir().join(format!("{}.wallet", random_string(32)));
-    let wallet = Wallet::get(temp_path.to_str().unwrap());
-    wallet
+    Wallet::get(temp_path.to_str().unwrap(), Network::Mainnet)
+}
+
+// Deterministic address no wallet holds the key of
+pub fn address(seed: &str) -> String {
+    Network::Mainnet.public_key_hash_address(&hash256(seed.as_bytes()))
 }
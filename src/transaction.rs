@@ -1,11 +1,34 @@
-use std::vec;
+use std::{cmp::Reverse, collections::HashSet, sync::Arc, vec};
 
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
-use crate::{hashable::Hashable, Wallet};
+use crate::{
+    address::Network,
+    error::Error,
+    hashable::Hashable,
+    script::{hash256, Op, Script, MAX_MULTISIG_KEYS},
+    Wallet,
+};
 
-const MINER_REWARD: u64 = 50;
+pub const MINER_REWARD: u64 = 50;
+// Sizes of a p256 signature and a compressed public key, used to estimate sizes before signing
+const SIGNATURE_SIZE: usize = 64;
+const PUBLIC_KEY_SIZE: usize = 33;
+// Upper bound of branches `BranchAndBound` explores before giving up
+const BNB_MAX_TRIES: usize = 100_000;
+// Largest payload a data output can carry
+pub const MAX_DATA_SIZE: usize = 80;
+// Lock times below this are block heights, and unix timestamps otherwise
+pub const LOCK_TIME_THRESHOLD: u64 = 500_000_000;
+// Longest relative locks outputs can have, the ranges of BIP 68
+pub const MAX_RELATIVE_LOCK_BLOCKS: u64 = 65_535;
+pub const MAX_RELATIVE_LOCK_SECONDS: u64 = 65_535 * 512;
+
+// Fee rates are expressed in coins per 1000 bytes of a transaction
+pub fn fee_for_size(size: usize, fee_rate: u64) -> u64 {
+    (size as u64 * fee_rate).div_ceil(1000)
+}
 
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
 pub struct Transaction {
@@ -13,38 +36,100 @@ pub struct Transaction {
     pub timestamp: i64,
     pub txn_ins: Vec<TxnIn>,
     pub txn_outs: Vec<TxnOut>,
+    #[serde(default)]
+    pub lock_time: u64,
+    // Opts in to being replaced by a conflicting transaction paying a higher fee
+    #[serde(default)]
+    pub replaceable: bool,
 }
 
 impl Transaction {
-    pub fn from_coinbase(address: &str) -> Self {
-        let mut coinbase_txn_in = TxnIn::new("", -1, MINER_REWARD);
-        coinbase_txn_in.set_signature("COINBASE");
+    // Pays the miner the reward plus the `fees` of the block's transactions
+    pub fn from_coinbase(address: &str, fees: u64) -> Self {
+        let mut coinbase_txn_in = TxnIn::new("", -1, MINER_REWARD + fees);
+        coinbase_txn_in.script_sig = Script::new(vec![Op::Push(b"COINBASE".to_vec())]);
         let txn_ins = vec![coinbase_txn_in];
-        let txn_outs = vec![TxnOut::new(address, MINER_REWARD)];
+        let txn_outs = vec![TxnOut::new(address, MINER_REWARD + fees)];
         let mut txn = Transaction::new(txn_ins, txn_outs);
         txn.hash = txn.hash();
         txn
     }
 
     pub fn new(txn_ins: Vec<TxnIn>, txn_outs: Vec<TxnOut>) -> Self {
-        let timestamp = Utc::now().timestamp_nanos();
+        let timestamp = Utc::now().timestamp_nanos_opt().unwrap();
         let mut txn = Transaction {
             hash: String::from(""),
             timestamp,
             txn_ins,
             txn_outs,
+            lock_time: 0,
+            replaceable: false,
         };
         txn.hash = txn.hash();
         txn
     }
 
+    pub fn set_lock_time(&mut self, lock_time: u64) {
+        self.lock_time = lock_time;
+        self.hash = self.hash();
+    }
+
+    pub fn set_replaceable(&mut self, replaceable: bool) {
+        self.replaceable = replaceable;
+        self.hash = self.hash();
+    }
+
+    // A transaction can only be mined in a block after its lock time,
+    // compared against the block height or the median time past
+    pub fn is_final(&self, height: u64, median_time: i64) -> bool {
+        if self.lock_time == 0 {
+            true
+        } else if self.lock_time < LOCK_TIME_THRESHOLD {
+            self.lock_time < height
+        } else {
+            (self.lock_time as i64) < median_time
+        }
+    }
+
     pub fn sign(&mut self, wallet: &Wallet) {
-        let msg = hex::encode(&self.hash);
-        let signature = wallet.sign(msg.as_str());
+        let signature = self.signature(wallet);
+        let public_key = wallet.public_key();
         for txn_in in &mut self.txn_ins {
-            txn_in.set_signature(&signature);
+            txn_in.sign(&signature, &public_key);
         }
     }
+
+    pub fn signature(&self, wallet: &Wallet) -> Vec<u8> {
+        let msg = hex::encode(&self.hash);
+        hex::decode(wallet.sign(msg.as_str())).unwrap()
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.txn_ins.len() == 1 && self.txn_ins[0].txn_hash.is_empty() && self.txn_ins[0].idx == -1
+    }
+
+    // Unlike `bytes`, which the hash is made of, these include the unlocking scripts
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.bytes();
+        for txn_in in self.txn_ins.iter() {
+            bytes.append(&mut txn_in.script_sig.bytes());
+        }
+        bytes
+    }
+
+    pub fn size(&self) -> usize {
+        self.signed_bytes().len()
+    }
+
+    pub fn fee(&self) -> u64 {
+        let total_in: u64 = self.txn_ins.iter().map(|txn_in| txn_in.amount).sum();
+        let total_out: u64 = self.txn_outs.iter().map(|txn_out| txn_out.amount).sum();
+        total_in.saturating_sub(total_out)
+    }
+
+    pub fn fee_rate(&self) -> u64 {
+        self.fee() * 1000 / self.size() as u64
+    }
 }
 
 impl Hashable for Transaction {
@@ -65,6 +150,13 @@ impl Hashable for Transaction {
                 .flat_map(|txn_out| txn_out.bytes())
                 .collect::<Vec<u8>>(),
         );
+        // Unlocked transactions keep the hashes they had before lock times existed
+        if self.lock_time != 0 {
+            bytes.append(&mut self.lock_time.to_le_bytes().to_vec());
+        }
+        if self.replaceable {
+            bytes.push(1);
+        }
         bytes
     }
 }
@@ -74,55 +166,260 @@ pub struct TxnIn {
     pub txn_hash: String,
     pub idx: i64,
     pub amount: u64,
-    pub signature: String,
+    pub script_sig: Script,
 }
 
 impl TxnIn {
     pub fn new(txn_hash: &str, idx: i64, amount: u64) -> Self {
         Self {
             txn_hash: txn_hash.to_string(),
-            idx: idx,
-            signature: String::from(""), // Unsignd yet
-            amount: amount,
+            idx,
+            amount,
+            script_sig: Script::default(), // Unsigned yet
         }
     }
 
-    pub fn set_signature(&mut self, signature: &str) {
-        self.signature = signature.to_string();
+    // Inputs spending a multisig already carry its redeem script, and collect
+    // a partial signature when the key is one of the multisig keys
+    pub fn sign(&mut self, signature: &[u8], public_key: &[u8]) {
+        let redeem_script = self
+            .script_sig
+            .pushed_data()
+            .last()
+            .and_then(|bytes| Script::from_bytes(bytes))
+            .filter(|script| script.multisig_keys().is_some());
+        match redeem_script {
+            Some(redeem_script) => {
+                let (_, public_keys) = redeem_script.multisig_keys().unwrap();
+                let signature = Op::Push(signature.to_vec());
+                if public_keys.contains(&public_key) && !self.script_sig.0.contains(&signature) {
+                    let redeem_idx = self.script_sig.0.len() - 1;
+                    self.script_sig.0.insert(redeem_idx, signature);
+                }
+            }
+            None => {
+                self.script_sig = Script::new(vec![
+                    Op::Push(signature.to_vec()),
+                    Op::Push(public_key.to_vec()),
+                ])
+            }
+        }
     }
 }
 
+// The unlocking script is left out, as it signs the hash
 impl Hashable for TxnIn {
     fn bytes(&self) -> Vec<u8> {
         let mut bytes = vec![];
         bytes.append(&mut self.txn_hash.clone().into_bytes());
         bytes.append(&mut self.idx.to_le_bytes().to_vec());
         bytes.append(&mut self.amount.to_le_bytes().to_vec());
-        bytes.append(&mut self.signature.clone().into_bytes());
+        bytes
+    }
+}
+
+// Locks funds to `required` signatures out of `public_keys`
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+pub struct MultiSig {
+    pub required: usize,
+    pub public_keys: Vec<String>,
+}
+
+impl MultiSig {
+    pub fn new(required: usize, public_keys: Vec<String>) -> Result<Self, Error> {
+        let multisig = Self {
+            required,
+            public_keys,
+        };
+        if !multisig.is_valid() {
+            return Err(Error::new("Invalid multisig"));
+        }
+        Ok(multisig)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let unique_keys: HashSet<&String> = self.public_keys.iter().collect();
+        self.required > 0
+            && self.required <= self.public_keys.len()
+            && self.public_keys.len() <= MAX_MULTISIG_KEYS
+            && unique_keys.len() == self.public_keys.len()
+            && self.public_keys.iter().all(|key| hex::decode(key).is_ok())
+    }
+
+    // Fails for multisigs deserialized without going through `new`
+    pub fn redeem_script(&self) -> Result<Script, Error> {
+        if !self.is_valid() {
+            return Err(Error::new("Invalid multisig"));
+        }
+        let public_keys = self
+            .public_keys
+            .iter()
+            .map(hex::decode)
+            .collect::<Result<Vec<Vec<u8>>, _>>()
+            .map_err(|_| Error::new("Invalid multisig"))?;
+        Ok(Script::multisig(self.required, &public_keys))
+    }
+
+    // Outputs are locked to the hash of the redeem script, which is only revealed when spending
+    pub fn script_hash(&self) -> Result<Vec<u8>, Error> {
+        Ok(hash256(&self.redeem_script()?.bytes()))
+    }
+
+    pub fn address(&self, network: Network) -> Result<String, Error> {
+        Ok(network.script_hash_address(&self.script_hash()?))
+    }
+}
+
+// Hash-time-locked contract, claimable by `recipient` with the SHA-256 preimage of `hash`
+// until `timeout`, and refundable to `refund` after it. Timeouts are read like lock times
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+pub struct Htlc {
+    pub hash: String,
+    pub recipient: String,
+    pub refund: String,
+    pub timeout: u64,
+}
+
+impl Htlc {
+    pub fn script(&self) -> Result<Script, Error> {
+        let hash = hex::decode(&self.hash)
+            .ok()
+            .filter(|hash| hash.len() == 32)
+            .ok_or_else(|| Error::new("HTLC hash must be a hex encoded SHA-256 hash"))?;
+        if self.timeout == 0 {
+            return Err(Error::new("HTLC timeout must be set"));
+        }
+        let recipient = Script::for_address(&self.recipient);
+        let refund = Script::for_address(&self.refund);
+        match (recipient.public_key_hash(), refund.public_key_hash()) {
+            (Some(recipient), Some(refund)) => {
+                Ok(Script::htlc(&hash, recipient, refund, self.timeout))
+            }
+            _ => Err(Error::new("HTLC addresses must pay to public key hashes")),
+        }
+    }
+
+    pub fn is_expired(timeout: u64, height: u64, median_time: i64) -> bool {
+        if timeout < LOCK_TIME_THRESHOLD {
+            height > timeout
+        } else {
+            median_time > timeout as i64
+        }
+    }
+}
+
+// Keeps an output unspendable until it has been confirmed for a while
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+pub enum RelativeLock {
+    Blocks(u64),
+    Seconds(u64),
+}
+
+impl RelativeLock {
+    pub fn is_satisfied(
+        &self,
+        confirmed_height: u64,
+        confirmed_time: i64,
+        height: u64,
+        median_time: i64,
+    ) -> bool {
+        // Locks out of range never unlock instead of wrapping around
+        match self {
+            RelativeLock::Blocks(blocks) => confirmed_height
+                .checked_add(*blocks)
+                .is_some_and(|unlock_height| height >= unlock_height),
+            RelativeLock::Seconds(seconds) => i64::try_from(*seconds)
+                .ok()
+                .and_then(|seconds| confirmed_time.checked_add(seconds))
+                .is_some_and(|unlock_time| median_time >= unlock_time),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            RelativeLock::Blocks(blocks) => *blocks <= MAX_RELATIVE_LOCK_BLOCKS,
+            RelativeLock::Seconds(seconds) => *seconds <= MAX_RELATIVE_LOCK_SECONDS,
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        let (kind, value) = match self {
+            RelativeLock::Blocks(blocks) => (1u8, blocks),
+            RelativeLock::Seconds(seconds) => (2u8, seconds),
+        };
+        let mut bytes = vec![kind];
+        bytes.append(&mut value.to_le_bytes().to_vec());
         bytes
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
 pub struct TxnOut {
-    pub address: String,
     pub amount: u64,
+    pub script_pubkey: Script,
+    #[serde(default)]
+    pub relative_lock: Option<RelativeLock>,
 }
 
 impl TxnOut {
     pub fn new(address: &str, amount: u64) -> Self {
         Self {
-            address: address.to_string(),
-            amount: amount,
+            amount,
+            script_pubkey: Script::for_address(address),
+            relative_lock: None,
+        }
+    }
+
+    pub fn with_relative_lock(address: &str, amount: u64, relative_lock: RelativeLock) -> Self {
+        Self {
+            amount,
+            script_pubkey: Script::for_address(address),
+            relative_lock: Some(relative_lock),
         }
     }
+
+    pub fn with_script(script_pubkey: Script, amount: u64) -> Self {
+        Self {
+            amount,
+            script_pubkey,
+            relative_lock: None,
+        }
+    }
+
+    pub fn data(payload: &[u8]) -> Self {
+        Self::with_script(Script::data(payload), 0)
+    }
+
+    // Data outputs must carry no coins and at most MAX_DATA_SIZE bytes,
+    // and other outputs can't be unspendable
+    pub fn is_valid(&self) -> bool {
+        if !self.script_pubkey.is_well_formed()
+            || self
+                .relative_lock
+                .as_ref()
+                .is_some_and(|relative_lock| !relative_lock.is_valid())
+        {
+            return false;
+        }
+        match self.script_pubkey.data_payload() {
+            Some(payload) => self.amount == 0 && payload.len() <= MAX_DATA_SIZE,
+            None => !self.script_pubkey.is_unspendable(),
+        }
+    }
+
+    pub fn is_locked_to(&self, address: &str) -> bool {
+        self.script_pubkey == Script::for_address(address)
+    }
 }
 
 impl Hashable for TxnOut {
     fn bytes(&self) -> Vec<u8> {
         let mut bytes = vec![];
-        bytes.append(&mut self.address.clone().into_bytes());
+        bytes.append(&mut self.script_pubkey.bytes());
         bytes.append(&mut self.amount.to_le_bytes().to_vec());
+        if let Some(relative_lock) = &self.relative_lock {
+            bytes.append(&mut relative_lock.bytes());
+        }
         bytes
     }
 }
@@ -139,8 +436,654 @@ impl UTxnOut {
     pub fn new(txn_hash: &str, idx: i64, amount: u64) -> Self {
         Self {
             txn_hash: txn_hash.to_string(),
-            idx: idx,
-            amount: amount,
+            idx,
+            amount,
+        }
+    }
+}
+
+// Entry of the UTXO set, which remembers the height of the block creating the output
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+pub struct UtxoEntry {
+    pub txn_hash: String,
+    pub idx: i64,
+    pub txn_out: TxnOut,
+    pub height: u64,
+}
+
+impl UtxoEntry {
+    pub fn new(txn_hash: &str, idx: i64, txn_out: TxnOut, height: u64) -> Self {
+        Self {
+            txn_hash: txn_hash.to_string(),
+            idx,
+            txn_out,
+            height,
+        }
+    }
+}
+
+pub trait CoinSelector: Send + Sync {
+    // Picks utxos whose total covers `target` plus `input_fee` for every picked utxo.
+    // `change_cost` is the fee of an extra change output, which selectors
+    // avoiding change can use as the tolerance of an exact match.
+    fn select(
+        &self,
+        utxos: &[UTxnOut],
+        target: u64,
+        input_fee: u64,
+        change_cost: u64,
+    ) -> Option<Vec<UTxnOut>>;
+}
+
+fn select_in_order(utxos: Vec<UTxnOut>, target: u64, input_fee: u64) -> Option<Vec<UTxnOut>> {
+    let mut selected = vec![];
+    let mut total = 0;
+    for utxo in utxos.into_iter() {
+        total += utxo.amount;
+        selected.push(utxo);
+        if total >= target + input_fee * selected.len() as u64 {
+            return Some(selected);
+        }
+    }
+    None
+}
+
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select(
+        &self,
+        utxos: &[UTxnOut],
+        target: u64,
+        input_fee: u64,
+        _change_cost: u64,
+    ) -> Option<Vec<UTxnOut>> {
+        let mut utxos = utxos.to_vec();
+        utxos.sort_by_key(|utxo| Reverse(utxo.amount));
+        select_in_order(utxos, target, input_fee)
+    }
+}
+
+pub struct SmallestFirst;
+
+impl CoinSelector for SmallestFirst {
+    fn select(
+        &self,
+        utxos: &[UTxnOut],
+        target: u64,
+        input_fee: u64,
+        _change_cost: u64,
+    ) -> Option<Vec<UTxnOut>> {
+        let mut utxos = utxos.to_vec();
+        utxos.sort_by_key(|utxo| utxo.amount);
+        select_in_order(utxos, target, input_fee)
+    }
+}
+
+// Searches for a set of utxos matching the target exactly (within `change_cost`),
+// so that no change output is needed. Returns None when there is no such set.
+pub struct BranchAndBound;
+
+struct BnbSearch {
+    values: Vec<u64>,
+    remaining: Vec<u64>,
+    target: u64,
+    upper: u64,
+    tries: usize,
+    best: Option<(u64, Vec<usize>)>,
+}
+
+impl BnbSearch {
+    fn search(&mut self, depth: usize, total: u64, picked: &mut Vec<usize>) {
+        self.tries += 1;
+        if self.tries > BNB_MAX_TRIES || total > self.upper {
+            return;
+        }
+        // Every transaction spends at least one utxo, even when the target is zero
+        if total >= self.target && !picked.is_empty() {
+            let waste = total - self.target;
+            if self.best.as_ref().is_none_or(|(best, _)| waste < *best) {
+                self.best = Some((waste, picked.clone()));
+            }
+            return;
         }
+        if depth == self.values.len() || total + self.remaining[depth] < self.target {
+            return;
+        }
+        picked.push(depth);
+        self.search(depth + 1, total + self.values[depth], picked);
+        picked.pop();
+        self.search(depth + 1, total, picked);
+    }
+}
+
+impl CoinSelector for BranchAndBound {
+    fn select(
+        &self,
+        utxos: &[UTxnOut],
+        target: u64,
+        input_fee: u64,
+        change_cost: u64,
+    ) -> Option<Vec<UTxnOut>> {
+        // Work with effective values, which already pay for their own input
+        let mut utxos: Vec<UTxnOut> = utxos
+            .iter()
+            .filter(|utxo| utxo.amount > input_fee)
+            .cloned()
+            .collect();
+        utxos.sort_by_key(|utxo| Reverse(utxo.amount));
+        let values: Vec<u64> = utxos.iter().map(|utxo| utxo.amount - input_fee).collect();
+        let mut remaining = vec![0; values.len()];
+        let mut sum = 0;
+        for idx in (0..values.len()).rev() {
+            sum += values[idx];
+            remaining[idx] = sum;
+        }
+        let mut search = BnbSearch {
+            values,
+            remaining,
+            target,
+            upper: target + change_cost,
+            tries: 0,
+            best: None,
+        };
+        search.search(0, 0, &mut vec![]);
+        search
+            .best
+            .map(|(_, picked)| picked.into_iter().map(|idx| utxos[idx].clone()).collect())
+    }
+}
+
+// Builds an unsigned transaction. Signing and mempool submission are left to the caller.
+#[derive(Clone)]
+pub struct TransactionBuilder {
+    utxos: Vec<UTxnOut>,
+    txn_outs: Vec<TxnOut>,
+    change_script: Option<Script>,
+    fee_rate: Option<u64>,
+    lock_time: u64,
+    replaceable: bool,
+    multisig: Option<MultiSig>,
+    coin_selector: Arc<dyn CoinSelector>,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> Self {
+        Self {
+            utxos: vec![],
+            txn_outs: vec![],
+            change_script: None,
+            fee_rate: None,
+            lock_time: 0,
+            replaceable: false,
+            multisig: None,
+            coin_selector: Arc::new(LargestFirst),
+        }
+    }
+
+    pub fn inputs(mut self, utxos: Vec<UTxnOut>) -> Self {
+        self.utxos = utxos;
+        self
+    }
+
+    pub fn output(mut self, address: &str, amount: u64) -> Self {
+        self.txn_outs.push(TxnOut::new(address, amount));
+        self
+    }
+
+    pub fn locked_output(
+        mut self,
+        address: &str,
+        amount: u64,
+        relative_lock: RelativeLock,
+    ) -> Self {
+        self.txn_outs
+            .push(TxnOut::with_relative_lock(address, amount, relative_lock));
+        self
+    }
+
+    pub fn script_output(mut self, script_pubkey: Script, amount: u64) -> Self {
+        self.txn_outs
+            .push(TxnOut::with_script(script_pubkey, amount));
+        self
+    }
+
+    pub fn data_output(mut self, payload: &[u8]) -> Self {
+        self.txn_outs.push(TxnOut::data(payload));
+        self
+    }
+
+    pub fn lock_time(mut self, lock_time: u64) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    pub fn replaceable(mut self, replaceable: bool) -> Self {
+        self.replaceable = replaceable;
+        self
+    }
+
+    // Spends utxos locked to `multisig`, whose address also receives the change
+    pub fn multisig(mut self, multisig: MultiSig) -> Self {
+        self.change_script = multisig
+            .script_hash()
+            .ok()
+            .map(|script_hash| Script::p2sh(&script_hash));
+        self.multisig = Some(multisig);
+        self
+    }
+
+    pub fn change_address(mut self, address: &str) -> Self {
+        self.change_script = Some(Script::for_address(address));
+        self
+    }
+
+    pub fn fee_rate(mut self, fee_rate: u64) -> Self {
+        self.fee_rate = Some(fee_rate);
+        self
+    }
+
+    // Fee rate to pay unless one was set explicitly
+    pub fn default_fee_rate(mut self, fee_rate: u64) -> Self {
+        self.fee_rate.get_or_insert(fee_rate);
+        self
+    }
+
+    pub fn coin_selector(mut self, coin_selector: Arc<dyn CoinSelector>) -> Self {
+        self.coin_selector = coin_selector;
+        self
+    }
+
+    pub fn payment(&self) -> u64 {
+        self.txn_outs.iter().map(|txn_out| txn_out.amount).sum()
+    }
+
+    pub fn build(&self) -> Result<Transaction, Error> {
+        if self.txn_outs.is_empty() {
+            return Err(Error::new("Transaction has no outputs"));
+        }
+        let payment = self.payment();
+        let redeem_script = self
+            .multisig
+            .as_ref()
+            .map(MultiSig::redeem_script)
+            .transpose()?;
+        let mut sized_txn = Transaction::new(vec![], self.txn_outs.clone());
+        sized_txn.set_lock_time(self.lock_time);
+        sized_txn.set_replaceable(self.replaceable);
+        let fee_rate = self.fee_rate.unwrap_or(0);
+        let base_fee = fee_for_size(sized_txn.size(), fee_rate);
+        let sized_txn_in = self.txn_in(
+            &UTxnOut::new(&"0".repeat(64), 0, 0),
+            redeem_script.as_ref(),
+            true,
+        );
+        let input_fee = fee_for_size(
+            sized_txn_in.bytes().len() + sized_txn_in.script_sig.bytes().len(),
+            fee_rate,
+        );
+        let change_script = self.change_script.clone().unwrap_or_default();
+        let change_cost = fee_for_size(
+            TxnOut::with_script(change_script, 0).bytes().len(),
+            fee_rate,
+        );
+
+        let selected = self
+            .coin_selector
+            .select(&self.utxos, payment + base_fee, input_fee, change_cost)
+            .ok_or_else(|| Error::new("Not enough balance"))?;
+        let total: u64 = selected.iter().map(|utxo| utxo.amount).sum();
+        // Selectors are pluggable, so what they picked may still fall short
+        let excess = total
+            .checked_sub(payment + base_fee + input_fee * selected.len() as u64)
+            .ok_or_else(|| Error::new("Not enough balance"))?;
+
+        let mut txn_outs = self.txn_outs.clone();
+        // Bring changes back to transaction sender, unless it costs more than it's worth
+        if excess > change_cost {
+            match &self.change_script {
+                Some(script) => {
+                    txn_outs.insert(0, TxnOut::with_script(script.clone(), excess - change_cost))
+                }
+                None => return Err(Error::new("Change address is required")),
+            }
+        }
+        if !txn_outs.iter().all(TxnOut::is_valid) {
+            return Err(Error::new("Invalid output"));
+        }
+        let txn_ins = selected
+            .iter()
+            .map(|utxo| self.txn_in(utxo, redeem_script.as_ref(), false))
+            .collect();
+        let mut txn = Transaction::new(txn_ins, txn_outs);
+        txn.set_lock_time(self.lock_time);
+        txn.set_replaceable(self.replaceable);
+        Ok(txn)
+    }
+
+    // Sized inputs carry placeholder signatures to estimate fees before signing
+    fn txn_in(&self, utxo: &UTxnOut, redeem_script: Option<&Script>, sized: bool) -> TxnIn {
+        let mut txn_in = TxnIn::new(&utxo.txn_hash, utxo.idx, utxo.amount);
+        let placeholder = Op::Push(vec![0; SIGNATURE_SIZE]);
+        match (&self.multisig, redeem_script) {
+            (Some(multisig), Some(redeem_script)) => {
+                let mut ops = vec![];
+                if sized {
+                    ops = vec![placeholder; multisig.required];
+                }
+                ops.push(Op::Push(redeem_script.bytes()));
+                txn_in.script_sig = Script::new(ops);
+            }
+            _ if sized => {
+                let public_key = Op::Push(vec![0; PUBLIC_KEY_SIZE]);
+                txn_in.script_sig = Script::new(vec![placeholder, public_key]);
+            }
+            _ => {}
+        }
+        txn_in
+    }
+}
+
+impl Default for TransactionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutils;
+
+    fn utxos(amounts: &[u64]) -> Vec<UTxnOut> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(idx, amount)| UTxnOut::new("txn_hash", idx as i64, *amount))
+            .collect()
+    }
+
+    fn amounts(utxos: &[UTxnOut]) -> Vec<u64> {
+        utxos.iter().map(|utxo| utxo.amount).collect()
+    }
+
+    #[test]
+    fn transaction_is_final_after_lock_time() {
+        let mut txn = Transaction::new(vec![], vec![TxnOut::new(&testutils::address("to"), 10)]);
+        assert!(txn.is_final(1, 0));
+
+        txn.set_lock_time(5);
+        assert!(!txn.is_final(5, 0));
+        assert!(txn.is_final(6, 0));
+
+        txn.set_lock_time(LOCK_TIME_THRESHOLD + 100);
+        assert!(!txn.is_final(1000, (LOCK_TIME_THRESHOLD + 100) as i64));
+        assert!(txn.is_final(1, (LOCK_TIME_THRESHOLD + 101) as i64));
+    }
+
+    #[test]
+    fn relative_lock_is_satisfied_after_confirmations() {
+        let lock = RelativeLock::Blocks(3);
+        assert!(!lock.is_satisfied(10, 0, 12, 0));
+        assert!(lock.is_satisfied(10, 0, 13, 0));
+
+        let lock = RelativeLock::Seconds(60);
+        assert!(!lock.is_satisfied(10, 1000, 20, 1059));
+        assert!(lock.is_satisfied(10, 1000, 11, 1060));
+
+        let lock = RelativeLock::Blocks(u64::MAX);
+        assert!(!lock.is_satisfied(10, 0, u64::MAX, 0));
+        let lock = RelativeLock::Seconds(u64::MAX);
+        assert!(!lock.is_satisfied(10, 1000, 11, i64::MAX));
+    }
+
+    #[test]
+    fn outputs_with_out_of_range_relative_lock_are_invalid() {
+        let address = testutils::address("to");
+        let lock = RelativeLock::Blocks(MAX_RELATIVE_LOCK_BLOCKS);
+        assert!(TxnOut::with_relative_lock(&address, 10, lock).is_valid());
+        let lock = RelativeLock::Blocks(MAX_RELATIVE_LOCK_BLOCKS + 1);
+        assert!(!TxnOut::with_relative_lock(&address, 10, lock).is_valid());
+        let lock = RelativeLock::Seconds(MAX_RELATIVE_LOCK_SECONDS);
+        assert!(TxnOut::with_relative_lock(&address, 10, lock).is_valid());
+        let lock = RelativeLock::Seconds(u64::MAX);
+        assert!(!TxnOut::with_relative_lock(&address, 10, lock).is_valid());
+    }
+
+    #[test]
+    fn multisig_requires_valid_threshold_and_unique_keys() {
+        let keys = vec![String::from("aa"), String::from("bb")];
+        assert!(MultiSig::new(2, keys.clone()).is_ok());
+        assert!(MultiSig::new(0, keys.clone()).is_err());
+        assert!(MultiSig::new(3, keys).is_err());
+        assert!(MultiSig::new(1, vec![String::from("aa"), String::from("aa")]).is_err());
+        assert!(MultiSig::new(1, vec![String::from("not-hex")]).is_err());
+    }
+
+    #[test]
+    fn multisig_address_depends_on_threshold_and_keys() {
+        let keys = vec![String::from("aa"), String::from("bb")];
+        let one_of_two = MultiSig::new(1, keys.clone()).unwrap();
+        let two_of_two = MultiSig::new(2, keys).unwrap();
+        assert_ne!(
+            one_of_two.address(Network::Mainnet).unwrap(),
+            two_of_two.address(Network::Mainnet).unwrap()
+        );
+    }
+
+    #[test]
+    fn multisig_without_valid_keys_has_no_redeem_script() {
+        let multisig = MultiSig {
+            required: 1,
+            public_keys: vec![String::from("not-hex")],
+        };
+        assert_eq!(
+            multisig.redeem_script().unwrap_err().msg,
+            "Invalid multisig"
+        );
+        assert!(multisig.address(Network::Mainnet).is_err());
+        let err = TransactionBuilder::new()
+            .inputs(utxos(&[50]))
+            .output(&testutils::address("to"), 20)
+            .multisig(multisig)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.msg, "Invalid multisig");
+    }
+
+    #[test]
+    fn largest_first_selects_largest_utxos() {
+        let selected = LargestFirst
+            .select(&utxos(&[10, 50, 30]), 60, 0, 0)
+            .unwrap();
+        assert_eq!(amounts(&selected), vec![50, 30]);
+    }
+
+    #[test]
+    fn smallest_first_selects_smallest_utxos() {
+        let selected = SmallestFirst
+            .select(&utxos(&[10, 50, 30]), 35, 0, 0)
+            .unwrap();
+        assert_eq!(amounts(&selected), vec![10, 30]);
+    }
+
+    #[test]
+    fn selectors_return_none_when_balance_is_not_enough() {
+        assert!(LargestFirst.select(&utxos(&[10, 20]), 31, 0, 0).is_none());
+        assert!(SmallestFirst.select(&utxos(&[10, 20]), 31, 0, 0).is_none());
+        assert!(BranchAndBound.select(&utxos(&[10, 20]), 31, 0, 0).is_none());
+    }
+
+    #[test]
+    fn branch_and_bound_finds_exact_match() {
+        let selected = BranchAndBound
+            .select(&utxos(&[50, 25, 15, 10]), 35, 0, 0)
+            .unwrap();
+        assert_eq!(amounts(&selected), vec![25, 10]);
+    }
+
+    #[test]
+    fn branch_and_bound_accounts_for_input_fee() {
+        // Each input pays 1, so 26 + 11 covers 35
+        let selected = BranchAndBound
+            .select(&utxos(&[50, 26, 15, 11]), 35, 1, 0)
+            .unwrap();
+        assert_eq!(amounts(&selected), vec![26, 11]);
+    }
+
+    #[test]
+    fn branch_and_bound_returns_none_without_exact_match() {
+        assert!(BranchAndBound.select(&utxos(&[50, 50]), 20, 0, 0).is_none());
+    }
+
+    #[test]
+    fn builder_adds_change_output_to_change_address() {
+        // When
+        let txn = TransactionBuilder::new()
+            .inputs(utxos(&[50]))
+            .output(&testutils::address("to"), 20)
+            .change_address(&testutils::address("from"))
+            .build()
+            .unwrap();
+
+        // Then
+        assert_eq!(txn.txn_ins.len(), 1);
+        assert_eq!(
+            txn.txn_outs[0],
+            TxnOut::new(&testutils::address("from"), 30)
+        );
+        assert_eq!(txn.txn_outs[1], TxnOut::new(&testutils::address("to"), 20));
+        assert_eq!(txn.fee(), 0);
+    }
+
+    #[test]
+    fn builder_pays_fee_by_fee_rate() {
+        // When
+        let txn = TransactionBuilder::new()
+            .inputs(utxos(&[50]))
+            .output(&testutils::address("to"), 20)
+            .change_address(&testutils::address("from"))
+            .fee_rate(10)
+            .build()
+            .unwrap();
+
+        // Then
+        assert!(txn.fee() > 0);
+        assert!(txn.fee_rate() >= 10);
+        assert_eq!(txn.txn_outs[0].amount + txn.fee(), 30);
+    }
+
+    #[test]
+    fn builder_skips_change_on_exact_match() {
+        // When
+        let txn = TransactionBuilder::new()
+            .inputs(utxos(&[50, 25, 10]))
+            .output(&testutils::address("to"), 35)
+            .change_address(&testutils::address("from"))
+            .coin_selector(Arc::new(BranchAndBound))
+            .build()
+            .unwrap();
+
+        // Then
+        assert_eq!(txn.txn_ins.len(), 2);
+        assert_eq!(
+            txn.txn_outs,
+            vec![TxnOut::new(&testutils::address("to"), 35)]
+        );
+    }
+
+    #[test]
+    fn builder_rejects_selection_short_of_target() {
+        // Given
+        struct FirstOnly;
+        impl CoinSelector for FirstOnly {
+            fn select(&self, utxos: &[UTxnOut], _: u64, _: u64, _: u64) -> Option<Vec<UTxnOut>> {
+                utxos.first().cloned().map(|utxo| vec![utxo])
+            }
+        }
+
+        // When
+        let err = TransactionBuilder::new()
+            .inputs(utxos(&[10, 50]))
+            .output(&testutils::address("to"), 20)
+            .change_address(&testutils::address("from"))
+            .coin_selector(Arc::new(FirstOnly))
+            .build()
+            .unwrap_err();
+
+        // Then
+        assert_eq!(err.msg, "Not enough balance");
+    }
+
+    #[test]
+    fn builder_requires_change_address_for_change() {
+        let err = TransactionBuilder::new()
+            .inputs(utxos(&[50]))
+            .output(&testutils::address("to"), 20)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.msg, "Change address is required");
+    }
+
+    #[test]
+    fn builder_funds_data_output_from_an_input() {
+        // When
+        let txn = TransactionBuilder::new()
+            .inputs(utxos(&[20]))
+            .data_output(b"document-hash")
+            .change_address(&testutils::address("from"))
+            .coin_selector(Arc::new(BranchAndBound))
+            .build();
+
+        // Then
+        assert!(txn.is_err()); // No exact match for a zero target
+        let txn = TransactionBuilder::new()
+            .inputs(utxos(&[20]))
+            .data_output(b"document-hash")
+            .change_address(&testutils::address("from"))
+            .build()
+            .unwrap();
+        assert_eq!(txn.txn_ins.len(), 1);
+        assert_eq!(
+            txn.txn_outs[0],
+            TxnOut::new(&testutils::address("from"), 20)
+        );
+        assert_eq!(
+            txn.txn_outs[1].script_pubkey.data_payload(),
+            Some(b"document-hash".as_slice())
+        );
+    }
+
+    #[test]
+    fn builder_rejects_oversized_data_output() {
+        let err = TransactionBuilder::new()
+            .inputs(utxos(&[20]))
+            .data_output(&[0; MAX_DATA_SIZE + 1])
+            .change_address(&testutils::address("from"))
+            .build()
+            .unwrap_err();
+        assert_eq!(err.msg, "Invalid output");
+    }
+
+    #[test]
+    fn builder_rejects_malformed_address() {
+        let address = testutils::address("to");
+        let typo = format!("{}x", &address[..address.len() - 1]);
+        let err = TransactionBuilder::new()
+            .inputs(utxos(&[20]))
+            .output(&typo, 20)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.msg, "Invalid output");
+    }
+
+    #[test]
+    fn builder_does_not_sign_transaction() {
+        let txn = TransactionBuilder::new()
+            .inputs(utxos(&[20]))
+            .output(&testutils::address("to"), 20)
+            .build()
+            .unwrap();
+        assert_eq!(txn.txn_ins[0].script_sig, Script::default());
     }
 }
@@ -5,6 +5,8 @@ use std::{
     path::Path,
 };
 
+use crate::{address::Network, script::hash256};
+
 pub struct Wallet {
     pub private_key: SigningKey,
     pub address: String,
@@ -17,6 +19,13 @@ fn read_key_from_file(filename: &str) -> SigningKey {
     SigningKey::from_bytes(&key_as_bytes).unwrap()
 }
 
+fn compressed_public_key(key: &SigningKey) -> Vec<u8> {
+    key.verifying_key()
+        .to_encoded_point(true)
+        .as_bytes()
+        .to_vec()
+}
+
 fn store_key_to_file(filename: &str, key: &SigningKey) -> std::io::Result<()> {
     let mut file = std::fs::File::create(filename)?;
     file.write_all(&key.to_bytes()).unwrap();
@@ -24,7 +33,7 @@ fn store_key_to_file(filename: &str, key: &SigningKey) -> std::io::Result<()> {
 }
 
 impl Wallet {
-    pub fn get(filename: &str) -> Self {
+    pub fn get(filename: &str, network: Network) -> Self {
         let private_key: SigningKey;
         if Path::new(filename).exists() {
             private_key = read_key_from_file(filename)
@@ -32,16 +41,19 @@ impl Wallet {
             private_key = SigningKey::random(&mut OsRng);
             store_key_to_file(filename, &private_key).unwrap();
         }
-        let address = private_key
-            .verifying_key()
-            .to_encoded_point(false)
-            .to_string();
+        let public_key = compressed_public_key(&private_key);
+        let address = network.public_key_hash_address(&hash256(&public_key));
         Wallet {
             private_key,
             address,
         }
     }
 
+    // Revealed by inputs spending from the wallet's address
+    pub fn public_key(&self) -> Vec<u8> {
+        compressed_public_key(&self.private_key)
+    }
+
     pub fn sign(&self, payload: &str) -> String {
         let msg_as_bytes = &hex::decode(payload).unwrap();
         self.private_key.sign(msg_as_bytes).to_string()
@@ -59,7 +71,7 @@ mod tests {
     fn get_new_wallet_creates_file() {
         let filename = testutils::random_string(16);
         // When
-        let wallet = Wallet::get(&filename);
+        let wallet = Wallet::get(&filename, Network::Mainnet);
 
         // Then
         assert!(Path::new(&filename).exists());
@@ -78,7 +90,7 @@ mod tests {
         store_key_to_file(&filename, &a_key).unwrap();
 
         // When
-        let wallet = Wallet::get(&filename);
+        let wallet = Wallet::get(&filename, Network::Mainnet);
 
         // Then
         assert_eq!(wallet.private_key, a_key);
@@ -87,18 +99,32 @@ mod tests {
         std::fs::remove_file(&filename).unwrap();
     }
 
+    #[test]
+    fn address_is_hash_of_compressed_public_key() {
+        // When
+        let wallet = testutils::test_wallet();
+
+        // Then
+        assert_eq!(wallet.public_key().len(), 33);
+        assert!(Network::Mainnet.validate(&wallet.address).is_ok());
+        assert_eq!(
+            crate::script::Script::for_address(&wallet.address).public_key_hash(),
+            Some(hash256(&wallet.public_key()).as_slice())
+        );
+    }
+
     #[test]
     fn sign_data() {
         // Given
         let filename = testutils::random_string(16);
-        let wallet = Wallet::get(&filename);
+        let wallet = Wallet::get(&filename, Network::Mainnet);
         let hashed_string = hex::encode("hello, world");
         // When
         let signature_as_string = wallet.sign(hashed_string.as_str());
 
         // Then
         let signature_as_bytes = &hex::decode(signature_as_string).unwrap();
-        let signature = Signature::from_bytes(&signature_as_bytes).unwrap();
+        let signature = Signature::from_bytes(signature_as_bytes).unwrap();
         let data = &hex::decode(hashed_string).unwrap();
         assert!(wallet
             .private_key